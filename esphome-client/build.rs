@@ -0,0 +1,653 @@
+extern crate prost_build;
+
+use heck::ToUpperCamelCase;
+use proc_macro2::{Span, TokenStream};
+use prost_reflect::{DescriptorPool, MessageDescriptor};
+use quote::quote;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::Ident;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/api.proto");
+    println!("cargo:rerun-if-changed=src/api_options.proto");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let descriptor_path = out_dir.join("api_descriptor.bin");
+    prost_build::Config::new()
+        .file_descriptor_set_path(&descriptor_path)
+        .compile_protos(&["src/api.proto"], &["src/"])
+        .unwrap();
+    let pool = DescriptorPool::decode(fs::read(&descriptor_path).unwrap().as_slice()).unwrap();
+
+    let msgs = parse_proto_messages(&pool);
+    let entities = extract_entity_types(&msgs);
+    check_messages(&msgs, &entities);
+
+    // again from the descriptors, now that we know which messages to gate
+    let mut config = prost_build::Config::new();
+    config
+        .file_descriptor_set_path(&descriptor_path)
+        .skip_protoc_run();
+    for msg in &msgs {
+        if let Some(feature) = message_feature(msg) {
+            let cfg = format!("#[cfg(feature = \"{feature}\")]");
+            config.message_attribute(format!(".{}", msg.desc.full_name()), cfg);
+        }
+    }
+    config
+        .compile_protos(&["src/api.proto"], &["src/"])
+        .unwrap();
+
+    let msg_enum = gen_message_type_enum(&msgs);
+    let entity_enum = gen_entity_type_enum(&entities);
+    let api_traits = gen_api_message_traits(&msgs);
+    let entity_message = gen_entity_message_trait(&msgs);
+    let any_message = gen_any_message(&msgs);
+    let metadata = gen_message_metadata(&msgs);
+    let entity_messages = gen_entity_messages_macro(&msgs, &entities);
+
+    let code = quote! {
+        // THIS IS GENERATED CODE - DO NOT MODIFY
+
+        use strum_macros::{Display, FromRepr};
+
+        #msg_enum
+
+        #entity_enum
+
+        #api_traits
+
+        #entity_message
+
+        #any_message
+
+        #metadata
+
+        #entity_messages
+    };
+    write_code(&out_dir.join("model.rs"), code);
+}
+
+fn write_code(path: &Path, code: TokenStream) {
+    let syntax = syn::parse2::<syn::File>(code).unwrap();
+    fs::write(path, prettyplease::unparse(&syntax)).unwrap();
+}
+
+/// A message with an `(id)`, so it can go over the wire
+struct ProtoMessage {
+    name: String,
+    id: u16,
+    /// `SOURCE_BOTH`, `SOURCE_SERVER` or `SOURCE_CLIENT`
+    source: String,
+    /// ESPHome define guarding the message, ie. `USE_LIGHT`
+    ifdef: Option<String>,
+    no_delay: bool,
+    desc: MessageDescriptor,
+}
+
+impl ProtoMessage {
+    fn has_field(&self, name: &str) -> bool {
+        self.desc.get_field_by_name(name).is_some()
+    }
+}
+
+/// The api.proto messages with an `(id)` option, in id order
+fn parse_proto_messages(pool: &DescriptorPool) -> Vec<ProtoMessage> {
+    let option = |name: &str| {
+        pool.get_extension_by_name(name)
+            .unwrap_or_else(|| panic!("api_options.proto has no `{name}` option"))
+    };
+    let (id_opt, source_opt) = (option("id"), option("source"));
+    let (ifdef_opt, no_delay_opt) = (option("ifdef"), option("no_delay"));
+
+    let mut msgs: Vec<ProtoMessage> = pool
+        .all_messages()
+        .filter(|desc| desc.parent_file().name() == "api.proto")
+        .filter_map(|desc| {
+            let options = desc.options();
+            if !options.has_extension(&id_opt) {
+                return None;
+            }
+            let name = desc.name().to_string();
+            let id = options.get_extension(&id_opt).as_u32().unwrap();
+            let id = u16::try_from(id)
+                .unwrap_or_else(|_| panic!("api.proto: `{name}` id {id} doesn't fit in a u16"));
+
+            let source = options.get_extension(&source_opt).as_enum_number().unwrap();
+            let source = source_opt
+                .kind()
+                .as_enum()
+                .unwrap()
+                .get_value(source)
+                .unwrap();
+            let ifdef = options.has_extension(&ifdef_opt).then(|| {
+                options
+                    .get_extension(&ifdef_opt)
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            });
+
+            Some(ProtoMessage {
+                id,
+                source: source.name().to_string(),
+                ifdef,
+                no_delay: options.get_extension(&no_delay_opt).as_bool().unwrap(),
+                desc,
+                name,
+            })
+        })
+        .collect();
+
+    msgs.sort_by_key(|msg| msg.id);
+    msgs
+}
+
+/// Entity types, from the `ListEntities<Entity>Response`s describing an entity
+/// (as opposed to ie. `ListEntitiesDoneResponse`)
+fn extract_entity_types(msgs: &[ProtoMessage]) -> Vec<String> {
+    msgs.iter()
+        .filter(|msg| msg.has_field("key") && msg.has_field("object_id"))
+        .filter_map(|msg| {
+            let entity = msg
+                .name
+                .strip_prefix("ListEntities")?
+                .strip_suffix("Response")?;
+            Some(entity.to_string())
+        })
+        .collect()
+}
+
+/// Fail the build on duplicate ids or entity messages that don't line up
+/// with a `ListEntities<Entity>Response`
+fn check_messages(msgs: &[ProtoMessage], entities: &[String]) {
+    let mut errors = Vec::new();
+
+    let mut by_id: HashMap<u16, &str> = HashMap::new();
+    for msg in msgs {
+        if let Some(other) = by_id.insert(msg.id, &msg.name) {
+            errors.push(format!(
+                "`{}` and `{other}` both have id {}",
+                msg.name, msg.id
+            ));
+        }
+    }
+
+    for entity in entities {
+        let command = format!("{entity}CommandRequest");
+        let has_command = msgs.iter().any(|msg| msg.name == command);
+        if state_message(msgs, entity).is_none() && !has_command {
+            errors.push(format!(
+                "`ListEntities{entity}Response` has no state message or `{command}`"
+            ));
+        }
+    }
+
+    // keyed state and command messages for an entity type that's never listed
+    for msg in msgs.iter().filter(|msg| msg.has_field("key")) {
+        let entity = msg
+            .name
+            .strip_suffix("StateResponse")
+            .or_else(|| msg.name.strip_suffix("CommandRequest"));
+        if let Some(entity) = entity
+            && !entities.iter().any(|e| e == entity)
+        {
+            errors.push(format!(
+                "`{}` has no matching `ListEntities{entity}Response`",
+                msg.name
+            ));
+        }
+    }
+
+    for (name, field) in SECRET_FIELDS {
+        if !msgs
+            .iter()
+            .any(|msg| msg.name == *name && msg.has_field(field))
+        {
+            errors.push(format!("secret field `{name}.{field}` doesn't exist"));
+        }
+    }
+
+    for (entity, state) in STATE_MESSAGE_OVERRIDES {
+        if !msgs.iter().any(|msg| msg.name == *state) {
+            errors.push(format!(
+                "state message `{state}` for `{entity}` doesn't exist"
+            ));
+        }
+    }
+
+    if !errors.is_empty() {
+        panic!("api.proto:\n  {}", errors.join("\n  "));
+    }
+}
+
+fn gen_message_type_enum(msgs: &[ProtoMessage]) -> TokenStream {
+    let variants = msgs.iter().map(|ProtoMessage { name, id, .. }| {
+        let ident = Ident::new(name, Span::call_site());
+        quote! { #ident = #id }
+    });
+
+    quote! {
+        #[derive(FromRepr, Display, Debug, PartialEq, Clone)]
+        #[repr(u16)]
+        pub enum MessageType {
+            #(#variants,)*
+        }
+    }
+}
+
+fn gen_message_metadata(msgs: &[ProtoMessage]) -> TokenStream {
+    let variants_where = |keep: &dyn Fn(&ProtoMessage) -> bool| {
+        msgs.iter()
+            .filter(|msg| keep(msg))
+            .map(|msg| Ident::new(&msg.name, Span::call_site()))
+            .collect::<Vec<_>>()
+    };
+    let server = variants_where(&|msg| msg.source == "SOURCE_SERVER");
+    let client = variants_where(&|msg| msg.source == "SOURCE_CLIENT");
+    let no_delay = variants_where(&|msg| msg.no_delay);
+
+    let ifdef_arms = msgs.iter().filter_map(|msg| {
+        let ifdef = msg.ifdef.as_ref()?;
+        let variant = Ident::new(&msg.name, Span::call_site());
+        Some(quote! { MessageType::#variant => Some(#ifdef) })
+    });
+    let disabled_checks = FEATURE_IFDEFS.iter().map(|(feature, ifdef)| {
+        quote! {
+            #[cfg(not(feature = #feature))]
+            if self.ifdef() == Some(#ifdef) {
+                return false;
+            }
+        }
+    });
+
+    quote! {
+        /// Which end of the connection sends a message, from its `source` option
+        #[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum MessageSource {
+            Both,
+            /// the device
+            Server,
+            /// us
+            Client,
+        }
+
+        impl MessageType {
+            /// Checked by [`Client::send_msg`](crate::client::Client::send_msg) and
+            /// [`Client::recv_msg`](crate::client::Client::recv_msg) only, connections
+            /// frame and write anything they're given
+            pub fn source(&self) -> MessageSource {
+                match self {
+                    #(MessageType::#server)|* => MessageSource::Server,
+                    #(MessageType::#client)|* => MessageSource::Client,
+                    _ => MessageSource::Both,
+                }
+            }
+
+            /// Whether the device may send it
+            pub fn from_device(&self) -> bool {
+                self.source() != MessageSource::Client
+            }
+
+            /// Whether we may send it
+            pub fn from_client(&self) -> bool {
+                self.source() != MessageSource::Server
+            }
+
+            /// ESPHome define the device needs for this message, ie. `USE_LIGHT`
+            pub fn ifdef(&self) -> Option<&'static str> {
+                match self {
+                    #(#ifdef_arms,)*
+                    _ => None,
+                }
+            }
+
+            /// Whether this build has the crate feature the message needs, if any
+            pub fn is_enabled(&self) -> bool {
+                #(#disabled_checks)*
+                true
+            }
+
+            /// Should be written right away instead of batched with what follows
+            pub fn no_delay(&self) -> bool {
+                matches!(self, #(MessageType::#no_delay)|*)
+            }
+        }
+    }
+}
+
+fn gen_entity_type_enum(entities: &[String]) -> TokenStream {
+    let variants = entities.iter().map(|name| {
+        let ident = Ident::new(name, Span::call_site());
+        quote! { #ident }
+    });
+
+    quote! {
+        #[derive(Display, Clone, Debug)]
+        #[strum(serialize_all = "snake_case")]
+        pub enum EntityType {
+            #(#variants,)*
+        }
+    }
+}
+
+/// Optional subsystems as cargo features, by the ESPHome define guarding their messages
+const FEATURE_IFDEFS: &[(&str, &str)] = &[
+    ("bluetooth", "USE_BLUETOOTH_PROXY"),
+    ("voice", "USE_VOICE_ASSISTANT"),
+    ("camera", "USE_ESP32_CAMERA"),
+    ("media", "USE_MEDIA_PLAYER"),
+];
+
+/// Cargo feature of the optional subsystem the message belongs to, if any
+fn message_feature(msg: &ProtoMessage) -> Option<&'static str> {
+    FEATURE_IFDEFS
+        .iter()
+        .find(|(_, ifdef)| msg.ifdef.as_deref() == Some(*ifdef))
+        .map(|(feature, _)| *feature)
+}
+
+/// `#[cfg(feature = ..)]` if the message belongs to an optional subsystem
+fn feature_cfg(msg: &ProtoMessage) -> TokenStream {
+    match message_feature(msg) {
+        Some(feature) => quote! { #[cfg(feature = #feature)] },
+        None => quote! {},
+    }
+}
+
+/// Requests answered by more than one message, or sent by the device
+const NOT_TRANSACTIONS: &[&str] = &[
+    "SubscribeLogsRequest",
+    "CameraImageRequest",
+    "BluetoothGATTGetServicesRequest",
+    "VoiceAssistantRequest",
+];
+
+fn gen_api_message_traits(msgs: &[ProtoMessage]) -> TokenStream {
+    let messages = msgs.iter().map(|msg| {
+        let variant = Ident::new(&msg.name, Span::call_site());
+        let ty = api_type(&msg.name);
+        let cfg = feature_cfg(msg);
+        quote! {
+            #cfg
+            impl ApiMessage for crate::api::#ty {
+                const MESSAGE_TYPE: MessageType = MessageType::#variant;
+            }
+        }
+    });
+
+    // FooRequest -> FooResponse
+    let requests = msgs.iter().filter_map(|req| {
+        let base = req.name.strip_suffix("Request")?;
+        if NOT_TRANSACTIONS.contains(&req.name.as_str()) {
+            return None;
+        }
+        let res_name = format!("{base}Response");
+        let res = msgs.iter().find(|other| other.name == res_name)?;
+        let req_ty = api_type(&req.name);
+        let res_ty = api_type(&res.name);
+        let (req_cfg, res_cfg) = (feature_cfg(req), feature_cfg(res));
+        Some(quote! {
+            #req_cfg
+            #res_cfg
+            impl Request for crate::api::#req_ty {
+                type Response = crate::api::#res_ty;
+            }
+        })
+    });
+
+    quote! {
+        /// An `api` message and the [`MessageType`] it's framed with
+        pub trait ApiMessage: prost::Message + Default {
+            const MESSAGE_TYPE: MessageType;
+        }
+
+        /// A message the device answers with exactly one [`Request::Response`]
+        pub trait Request: ApiMessage {
+            type Response: ApiMessage;
+        }
+
+        #(#messages)*
+
+        #(#requests)*
+    }
+}
+
+/// `EntityMessage` for every message with a `key` and `device_id`
+fn gen_entity_message_trait(msgs: &[ProtoMessage]) -> TokenStream {
+    let impls = msgs
+        .iter()
+        .filter(|msg| msg.has_field("key") && msg.has_field("device_id"))
+        .map(|msg| {
+            let ty = api_type(&msg.name);
+            let cfg = feature_cfg(msg);
+            quote! {
+                #cfg
+                impl EntityMessage for crate::api::#ty {
+                    fn entity_key(&self) -> crate::client::EntityKey {
+                        crate::client::EntityKey::new(self.device_id, self.key)
+                    }
+                }
+            }
+        });
+
+    quote! {
+        /// An `api` message about one entity
+        pub trait EntityMessage {
+            fn entity_key(&self) -> crate::client::EntityKey;
+        }
+
+        #(#impls)*
+    }
+}
+
+/// prost renames ie. `BluetoothLEAdvertisementResponse` -> `BluetoothLeAdvertisementResponse`
+fn api_type(name: &str) -> Ident {
+    Ident::new(&name.to_upper_camel_case(), Span::call_site())
+}
+
+/// Passwords and keys, never written to captures or debug output
+const SECRET_FIELDS: &[(&str, &str)] = &[
+    ("ConnectRequest", "password"),
+    ("NoiseEncryptionSetKeyRequest", "key"),
+];
+
+fn gen_any_message(msgs: &[ProtoMessage]) -> TokenStream {
+    let variants = msgs.iter().map(|msg| {
+        let variant = Ident::new(&msg.name, Span::call_site());
+        let ty = api_type(&msg.name);
+        let cfg = feature_cfg(msg);
+        quote! { #cfg #variant(crate::api::#ty) }
+    });
+    let decode_arms = msgs.iter().map(|msg| {
+        let variant = Ident::new(&msg.name, Span::call_site());
+        let ty = api_type(&msg.name);
+        let cfg = feature_cfg(msg);
+        quote! {
+            #cfg
+            MessageType::#variant => AnyMessage::#variant(crate::api::#ty::decode(bytes)?),
+        }
+    });
+    // `#cfg Variant` pairs, for the matches over every message
+    let (variant_cfgs, variant_idents): (Vec<_>, Vec<_>) = msgs
+        .iter()
+        .map(|msg| (feature_cfg(msg), Ident::new(&msg.name, Span::call_site())))
+        .unzip();
+
+    let mut redact_arms = Vec::new();
+    let mut secret_variants = Vec::new();
+    for msg in msgs {
+        let fields: Vec<_> = SECRET_FIELDS
+            .iter()
+            .filter(|(name, _)| *name == msg.name)
+            .map(|(_, field)| Ident::new(field, Span::call_site()))
+            .collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let variant = Ident::new(&msg.name, Span::call_site());
+        let cfg = feature_cfg(msg);
+        redact_arms.push(quote! {
+            #cfg
+            AnyMessage::#variant(msg) => {
+                #(msg.#fields = Default::default();)*
+            }
+        });
+        secret_variants.push(quote! { MessageType::#variant });
+    }
+
+    quote! {
+        /// Any `api` message, decoded by [`decode_message`]
+        #[derive(Clone, PartialEq)]
+        pub enum AnyMessage {
+            #(#variants,)*
+        }
+
+        impl AnyMessage {
+            pub fn message_type(&self) -> MessageType {
+                match self {
+                    #(#variant_cfgs AnyMessage::#variant_idents(_) => MessageType::#variant_idents,)*
+                }
+            }
+
+            /// Blank out passwords and keys
+            pub fn redact(&mut self) {
+                match self {
+                    #(#redact_arms)*
+                    _ => {}
+                }
+            }
+
+            pub fn encode_to_vec(&self) -> Vec<u8> {
+                use prost::Message;
+                match self {
+                    #(#variant_cfgs AnyMessage::#variant_idents(msg) => msg.encode_to_vec(),)*
+                }
+            }
+        }
+
+        /// Just the message, without the variant around it
+        impl std::fmt::Debug for AnyMessage {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    #(#variant_cfgs AnyMessage::#variant_idents(msg) => std::fmt::Debug::fmt(msg, f),)*
+                }
+            }
+        }
+
+        impl MessageType {
+            /// Whether the message carries passwords or keys, see [`AnyMessage::redact`]
+            pub fn has_secrets(&self) -> bool {
+                matches!(self, #(#secret_variants)|*)
+            }
+        }
+
+        /// Decode a message body into its `api` type, `None` if the
+        /// message's feature isn't enabled (see [`MessageType::is_enabled`])
+        pub fn decode_message(
+            msg_type: &MessageType,
+            bytes: &[u8],
+        ) -> Result<Option<AnyMessage>, prost::DecodeError> {
+            use prost::Message;
+            Ok(Some(match msg_type {
+                #(#decode_arms)*
+                #[allow(unreachable_patterns)]
+                _ => return Ok(None),
+            }))
+        }
+
+        /// Decode a message body and pretty print it, secrets blanked out
+        pub fn debug_message(
+            msg_type: &MessageType,
+            bytes: &[u8],
+        ) -> Result<String, prost::DecodeError> {
+            Ok(match decode_message(msg_type, bytes)? {
+                Some(mut msg) => {
+                    msg.redact();
+                    format!("{msg:#?}")
+                }
+                None => "(its feature isn't enabled in this build)".to_string(),
+            })
+        }
+
+        /// The message body with its passwords and keys blanked out,
+        /// `None` if `msg_type` has none
+        pub fn redact(msg_type: &MessageType, bytes: &[u8]) -> Option<Vec<u8>> {
+            if !msg_type.has_secrets() {
+                return None;
+            }
+            Some(match decode_message(msg_type, bytes) {
+                Ok(Some(mut msg)) => {
+                    msg.redact();
+                    msg.encode_to_vec()
+                }
+                // no telling where the secret is
+                _ => Vec::new(),
+            })
+        }
+    }
+}
+
+/// Entities whose updates aren't a `<Entity>StateResponse`
+const STATE_MESSAGE_OVERRIDES: &[(&str, &str)] = &[
+    ("Event", "EventResponse"),
+    ("Camera", "CameraImageResponse"),
+];
+
+/// The message an entity type's updates come in, if it has any
+fn state_message<'a>(msgs: &'a [ProtoMessage], entity: &str) -> Option<&'a ProtoMessage> {
+    let name = match STATE_MESSAGE_OVERRIDES.iter().find(|(e, _)| *e == entity) {
+        Some((_, name)) => name.to_string(),
+        None => format!("{entity}StateResponse"),
+    };
+    msgs.iter().find(|msg| msg.name == name)
+}
+
+/// `entity_messages!`, handing every entity type's listing and state message
+/// to a callback macro. Entries for optional subsystems are behind their
+/// cargo feature, checked in the calling crate.
+fn gen_entity_messages_macro(msgs: &[ProtoMessage], entities: &[String]) -> TokenStream {
+    let mut listed = Vec::new();
+    let mut updates = Vec::new();
+
+    for entity in entities {
+        let entity_type = Ident::new(entity, Span::call_site());
+        let list_name = format!("ListEntities{entity}Response");
+        let list = msgs.iter().find(|msg| msg.name == list_name).unwrap();
+        let list_variant = Ident::new(&list_name, Span::call_site());
+        let cfg = feature_cfg(list);
+        listed.push(quote! { #cfg #entity_type => #list_variant, });
+
+        if let Some(state) = state_message(msgs, entity) {
+            let state_variant = Ident::new(&state.name, Span::call_site());
+            let cfg = feature_cfg(state);
+            updates.push(quote! { #cfg #entity_type => #state_variant, });
+        }
+    }
+
+    quote! {
+        /// Calls `$callback!` with every entity type's `ListEntities*Response`
+        /// and the message its state updates come in, for generating per-entity
+        /// glue. `#[cfg(feature = ..)]`s on optional subsystems are checked in
+        /// the calling crate, so it needs the same features.
+        ///
+        /// ```ignore
+        /// $callback! {
+        ///     listed { Light => ListEntitiesLightResponse, .. }
+        ///     updates { Light => LightStateResponse, .. }
+        /// }
+        /// ```
+        #[macro_export]
+        macro_rules! entity_messages {
+            ($callback:ident) => {
+                $callback! {
+                    listed { #(#listed)* }
+                    updates { #(#updates)* }
+                }
+            };
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Print a capture written by a device's `capture` option, optionally
+//! feeding it through a [`Client`] to reproduce bugs offline. `igloo-replay`
+//! in igloo-esphome feeds it through the extension's `Device` instead.
+
+use esphome_client::{
+    api,
+    client::{Client, EntityState},
+    connection::{
+        capture::{self, Direction},
+        plain::PlainConnection,
+        timeouts::Timeouts,
+    },
+    model::{self, MessageType},
+};
+use prost::Message;
+use std::process::exit;
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "usage: esphome-replay <capture> [--process]
+
+  --process  feed messages from the device through Client, printing the entity states it tracks";
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut path = None;
+    let mut process = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--process" => process = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                exit(2);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("reading {path}: {e}");
+            exit(1);
+        }
+    };
+
+    // never connected, only fed the capture
+    let mut client = process
+        .then(|| Client::new(PlainConnection::new(String::new(), Timeouts::default()).into()));
+
+    let mut start = None;
+    for (line, record) in capture::read_capture(&content).enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("line {}: {e}", line + 1);
+                continue;
+            }
+        };
+        let bytes = match record.bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("line {}: invalid data: {e}", line + 1);
+                continue;
+            }
+        };
+
+        let start = *start.get_or_insert(record.time_us);
+        let elapsed = record.time_us.saturating_sub(start) as f64 / 1_000_000.;
+        let arrow = match record.dir {
+            Direction::Rx => "<-",
+            Direction::Tx => "->",
+        };
+
+        let Some(msg_type) = record.msg_type() else {
+            println!(
+                "{elapsed:>10.3}s {arrow} unknown message {} ({} bytes)",
+                record.msg_id,
+                bytes.len()
+            );
+            continue;
+        };
+        println!(
+            "{elapsed:>10.3}s {arrow} {msg_type} ({} bytes)",
+            bytes.len()
+        );
+        match model::debug_message(&msg_type, &bytes) {
+            Ok(debug) => println!("{}", indent(&debug)),
+            Err(e) => println!("    decode error: {e}"),
+        }
+
+        if let Some(client) = &mut client
+            && record.dir == Direction::Rx
+        {
+            process_msg(client, &msg_type, &bytes);
+        }
+    }
+}
+
+/// What `Client` makes of a message from the device
+fn process_msg(client: &mut Client, msg_type: &MessageType, bytes: &[u8]) {
+    if *msg_type == MessageType::HelloResponse {
+        match api::HelloResponse::decode(bytes) {
+            Ok(hello) => {
+                client.set_hello(hello);
+                println!(
+                    "    => api {} on {}",
+                    client.api_version(),
+                    client.server_info()
+                );
+            }
+            Err(e) => println!("    client error: {e}"),
+        }
+        return;
+    }
+
+    let Some(Ok(state)) = EntityState::decode(msg_type, bytes) else {
+        return;
+    };
+    let key = state.entity_key();
+    let changed = client.state(key) != Some(&state);
+    client.update_state(msg_type, bytes);
+    if changed {
+        println!("    => state of {key:?} changed");
+    } else {
+        println!("    => state of {key:?} unchanged");
+    }
+}
+
+fn indent(s: &str) -> String {
+    s.lines()
+        .map(|line| format!("    {line}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
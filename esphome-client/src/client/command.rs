@@ -0,0 +1,515 @@
+use std::time::Duration;
+
+use super::{ApiVersion, Client, ClientError, EntityKey};
+use crate::{api, model::ApiMessage};
+
+/// Before this, covers only accept `legacy_command`
+pub const COVER_POSITION_API_VERSION: ApiVersion = ApiVersion::new(1, 1);
+/// Before this, fans only accept the 3 step `speed`
+pub const FAN_SPEED_LEVEL_API_VERSION: ApiVersion = ApiVersion::new(1, 4);
+/// Before this, lights use the `legacy_supports_*` flags and have no color modes
+pub const COLOR_MODE_API_VERSION: ApiVersion = ApiVersion::new(1, 6);
+
+/// A command for one entity, built up then sent with [`Command::send`]
+#[must_use = "commands do nothing until sent"]
+pub struct Command<'a, R> {
+    client: &'a mut Client,
+    req: R,
+}
+
+impl<'a, R: ApiMessage> Command<'a, R> {
+    fn new(client: &'a mut Client, req: R) -> Self {
+        Self { client, req }
+    }
+
+    /// The request as built so far
+    pub fn request(&self) -> &R {
+        &self.req
+    }
+
+    pub async fn send(self) -> Result<(), ClientError> {
+        self.client.send(&self.req).await
+    }
+}
+
+macro_rules! command_constructors {
+    ($($(#[$cfg:meta])* $name:ident => $req:ident),* $(,)?) => {
+        impl Client {
+            $(
+                $(#[$cfg])*
+                pub fn $name(&mut self, key: impl Into<EntityKey>) -> Command<'_, api::$req> {
+                    let EntityKey { device_id, key } = key.into();
+                    Command::new(self, api::$req { key, device_id, ..Default::default() })
+                }
+            )*
+
+            /// Sending it presses the button
+            pub fn button(
+                &mut self,
+                key: impl Into<EntityKey>,
+            ) -> Command<'_, api::ButtonCommandRequest> {
+                let EntityKey { device_id, key } = key.into();
+                Command::new(self, api::ButtonCommandRequest { key, device_id })
+            }
+        }
+    };
+}
+
+command_constructors! {
+    light => LightCommandRequest,
+    switch => SwitchCommandRequest,
+    number => NumberCommandRequest,
+    select => SelectCommandRequest,
+    text => TextCommandRequest,
+    fan => FanCommandRequest,
+    cover => CoverCommandRequest,
+    valve => ValveCommandRequest,
+    siren => SirenCommandRequest,
+    lock => LockCommandRequest,
+    #[cfg(feature = "media")]
+    media_player => MediaPlayerCommandRequest,
+    climate => ClimateCommandRequest,
+    date => DateCommandRequest,
+    time => TimeCommandRequest,
+    date_time => DateTimeCommandRequest,
+    alarm_control_panel => AlarmControlPanelCommandRequest,
+    update => UpdateCommandRequest,
+}
+
+impl Command<'_, api::LightCommandRequest> {
+    pub fn turn_on(self) -> Self {
+        self.state(true)
+    }
+
+    pub fn turn_off(self) -> Self {
+        self.state(false)
+    }
+
+    pub fn state(mut self, state: bool) -> Self {
+        self.req.has_state = true;
+        self.req.state = state;
+        self
+    }
+
+    /// 0.0 - 1.0
+    pub fn brightness(mut self, brightness: f32) -> Self {
+        self.req.has_brightness = true;
+        self.req.brightness = brightness;
+        self
+    }
+
+    /// ESPHome `ColorMode` value. Left out on devices older than
+    /// [`COLOR_MODE_API_VERSION`], which go by the fields that are set.
+    pub fn color_mode(mut self, mode: i32) -> Self {
+        if self.client.api_version() >= COLOR_MODE_API_VERSION {
+            self.req.has_color_mode = true;
+            self.req.color_mode = mode;
+        }
+        self
+    }
+
+    /// 0.0 - 1.0, brightness of the color channels only
+    pub fn color_brightness(mut self, brightness: f32) -> Self {
+        self.req.has_color_brightness = true;
+        self.req.color_brightness = brightness;
+        self
+    }
+
+    /// Each 0.0 - 1.0
+    pub fn rgb(mut self, red: f32, green: f32, blue: f32) -> Self {
+        self.req.has_rgb = true;
+        self.req.red = red;
+        self.req.green = green;
+        self.req.blue = blue;
+        self
+    }
+
+    pub fn white(mut self, white: f32) -> Self {
+        self.req.has_white = true;
+        self.req.white = white;
+        self
+    }
+
+    /// In mireds
+    pub fn color_temperature(mut self, mireds: f32) -> Self {
+        self.req.has_color_temperature = true;
+        self.req.color_temperature = mireds;
+        self
+    }
+
+    pub fn cold_white(mut self, cold_white: f32) -> Self {
+        self.req.has_cold_white = true;
+        self.req.cold_white = cold_white;
+        self
+    }
+
+    pub fn warm_white(mut self, warm_white: f32) -> Self {
+        self.req.has_warm_white = true;
+        self.req.warm_white = warm_white;
+        self
+    }
+
+    pub fn transition(mut self, length: Duration) -> Self {
+        self.req.has_transition_length = true;
+        self.req.transition_length = length.as_millis().try_into().unwrap_or(u32::MAX);
+        self
+    }
+
+    pub fn flash(mut self, length: Duration) -> Self {
+        self.req.has_flash_length = true;
+        self.req.flash_length = length.as_millis().try_into().unwrap_or(u32::MAX);
+        self
+    }
+
+    pub fn effect(mut self, effect: impl Into<String>) -> Self {
+        self.req.has_effect = true;
+        self.req.effect = effect.into();
+        self
+    }
+}
+
+impl Command<'_, api::SwitchCommandRequest> {
+    pub fn turn_on(self) -> Self {
+        self.state(true)
+    }
+
+    pub fn turn_off(self) -> Self {
+        self.state(false)
+    }
+
+    pub fn state(mut self, state: bool) -> Self {
+        self.req.state = state;
+        self
+    }
+}
+
+impl Command<'_, api::NumberCommandRequest> {
+    pub fn set(mut self, value: f32) -> Self {
+        self.req.state = value;
+        self
+    }
+}
+
+impl Command<'_, api::SelectCommandRequest> {
+    pub fn set(mut self, option: impl Into<String>) -> Self {
+        self.req.state = option.into();
+        self
+    }
+}
+
+impl Command<'_, api::TextCommandRequest> {
+    pub fn set(mut self, text: impl Into<String>) -> Self {
+        self.req.state = text.into();
+        self
+    }
+}
+
+impl Command<'_, api::FanCommandRequest> {
+    pub fn turn_on(self) -> Self {
+        self.state(true)
+    }
+
+    pub fn turn_off(self) -> Self {
+        self.state(false)
+    }
+
+    pub fn state(mut self, state: bool) -> Self {
+        self.req.has_state = true;
+        self.req.state = state;
+        self
+    }
+
+    /// Mapped to low/medium/high on devices older than [`FAN_SPEED_LEVEL_API_VERSION`]
+    #[allow(deprecated)]
+    pub fn speed_level(mut self, level: i32) -> Self {
+        if self.client.api_version() < FAN_SPEED_LEVEL_API_VERSION {
+            self.req.has_speed = true;
+            self.req.speed = match level {
+                ..=1 => api::FanSpeed::Low,
+                2 => api::FanSpeed::Medium,
+                _ => api::FanSpeed::High,
+            }
+            .into();
+        } else {
+            self.req.has_speed_level = true;
+            self.req.speed_level = level;
+        }
+        self
+    }
+
+    pub fn oscillating(mut self, oscillating: bool) -> Self {
+        self.req.has_oscillating = true;
+        self.req.oscillating = oscillating;
+        self
+    }
+
+    pub fn direction(mut self, direction: api::FanDirection) -> Self {
+        self.req.has_direction = true;
+        self.req.direction = direction.into();
+        self
+    }
+
+    pub fn preset_mode(mut self, preset: impl Into<String>) -> Self {
+        self.req.has_preset_mode = true;
+        self.req.preset_mode = preset.into();
+        self
+    }
+}
+
+impl Command<'_, api::CoverCommandRequest> {
+    pub fn open(self) -> Self {
+        self.legacy_or_position(api::LegacyCoverCommand::Open, 1.)
+    }
+
+    pub fn close(self) -> Self {
+        self.legacy_or_position(api::LegacyCoverCommand::Close, 0.)
+    }
+
+    pub fn stop(mut self) -> Self {
+        if self.client.api_version() < COVER_POSITION_API_VERSION {
+            self.req.has_legacy_command = true;
+            self.req.legacy_command = api::LegacyCoverCommand::Stop.into();
+        } else {
+            self.req.stop = true;
+        }
+        self
+    }
+
+    /// 0.0 (closed) - 1.0 (open)
+    pub fn set_position(mut self, position: f32) -> Self {
+        self.req.has_position = true;
+        self.req.position = position;
+        self
+    }
+
+    /// 0.0 (closed) - 1.0 (open)
+    pub fn set_tilt(mut self, tilt: f32) -> Self {
+        self.req.has_tilt = true;
+        self.req.tilt = tilt;
+        self
+    }
+
+    fn legacy_or_position(mut self, legacy: api::LegacyCoverCommand, position: f32) -> Self {
+        if self.client.api_version() < COVER_POSITION_API_VERSION {
+            self.req.has_legacy_command = true;
+            self.req.legacy_command = legacy.into();
+            self
+        } else {
+            self.set_position(position)
+        }
+    }
+}
+
+impl Command<'_, api::ValveCommandRequest> {
+    pub fn open(self) -> Self {
+        self.set_position(1.)
+    }
+
+    pub fn close(self) -> Self {
+        self.set_position(0.)
+    }
+
+    pub fn stop(mut self) -> Self {
+        self.req.stop = true;
+        self
+    }
+
+    /// 0.0 (closed) - 1.0 (open)
+    pub fn set_position(mut self, position: f32) -> Self {
+        self.req.has_position = true;
+        self.req.position = position;
+        self
+    }
+}
+
+impl Command<'_, api::SirenCommandRequest> {
+    pub fn turn_on(self) -> Self {
+        self.state(true)
+    }
+
+    pub fn turn_off(self) -> Self {
+        self.state(false)
+    }
+
+    pub fn state(mut self, state: bool) -> Self {
+        self.req.has_state = true;
+        self.req.state = state;
+        self
+    }
+
+    pub fn tone(mut self, tone: impl Into<String>) -> Self {
+        self.req.has_tone = true;
+        self.req.tone = tone.into();
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.req.has_duration = true;
+        self.req.duration = duration.as_secs().try_into().unwrap_or(u32::MAX);
+        self
+    }
+
+    /// 0.0 - 1.0
+    pub fn volume(mut self, volume: f32) -> Self {
+        self.req.has_volume = true;
+        self.req.volume = volume;
+        self
+    }
+}
+
+impl Command<'_, api::LockCommandRequest> {
+    pub fn lock(self) -> Self {
+        self.command(api::LockCommand::LockLock)
+    }
+
+    pub fn unlock(self) -> Self {
+        self.command(api::LockCommand::LockUnlock)
+    }
+
+    pub fn open(self) -> Self {
+        self.command(api::LockCommand::LockOpen)
+    }
+
+    pub fn command(mut self, command: api::LockCommand) -> Self {
+        self.req.command = command.into();
+        self
+    }
+
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.req.has_code = true;
+        self.req.code = code.into();
+        self
+    }
+}
+
+#[cfg(feature = "media")]
+impl Command<'_, api::MediaPlayerCommandRequest> {
+    pub fn command(mut self, command: api::MediaPlayerCommand) -> Self {
+        self.req.has_command = true;
+        self.req.command = command.into();
+        self
+    }
+
+    /// 0.0 - 1.0
+    pub fn volume(mut self, volume: f32) -> Self {
+        self.req.has_volume = true;
+        self.req.volume = volume;
+        self
+    }
+
+    pub fn play_url(mut self, url: impl Into<String>) -> Self {
+        self.req.has_media_url = true;
+        self.req.media_url = url.into();
+        self
+    }
+
+    /// Play [`Self::play_url`] over whatever is playing, instead of replacing it
+    pub fn announcement(mut self, announcement: bool) -> Self {
+        self.req.has_announcement = true;
+        self.req.announcement = announcement;
+        self
+    }
+}
+
+impl Command<'_, api::ClimateCommandRequest> {
+    pub fn mode(mut self, mode: api::ClimateMode) -> Self {
+        self.req.has_mode = true;
+        self.req.mode = mode.into();
+        self
+    }
+
+    pub fn target_temperature(mut self, temperature: f32) -> Self {
+        self.req.has_target_temperature = true;
+        self.req.target_temperature = temperature;
+        self
+    }
+
+    /// For two point climates
+    pub fn target_temperature_range(mut self, low: f32, high: f32) -> Self {
+        self.req.has_target_temperature_low = true;
+        self.req.target_temperature_low = low;
+        self.req.has_target_temperature_high = true;
+        self.req.target_temperature_high = high;
+        self
+    }
+
+    pub fn target_humidity(mut self, humidity: f32) -> Self {
+        self.req.has_target_humidity = true;
+        self.req.target_humidity = humidity;
+        self
+    }
+
+    pub fn fan_mode(mut self, fan_mode: api::ClimateFanMode) -> Self {
+        self.req.has_fan_mode = true;
+        self.req.fan_mode = fan_mode.into();
+        self
+    }
+
+    pub fn custom_fan_mode(mut self, fan_mode: impl Into<String>) -> Self {
+        self.req.has_custom_fan_mode = true;
+        self.req.custom_fan_mode = fan_mode.into();
+        self
+    }
+
+    pub fn swing_mode(mut self, swing_mode: api::ClimateSwingMode) -> Self {
+        self.req.has_swing_mode = true;
+        self.req.swing_mode = swing_mode.into();
+        self
+    }
+
+    pub fn preset(mut self, preset: api::ClimatePreset) -> Self {
+        self.req.has_preset = true;
+        self.req.preset = preset.into();
+        self
+    }
+
+    pub fn custom_preset(mut self, preset: impl Into<String>) -> Self {
+        self.req.has_custom_preset = true;
+        self.req.custom_preset = preset.into();
+        self
+    }
+}
+
+impl Command<'_, api::DateCommandRequest> {
+    pub fn set(mut self, year: u32, month: u32, day: u32) -> Self {
+        self.req.year = year;
+        self.req.month = month;
+        self.req.day = day;
+        self
+    }
+}
+
+impl Command<'_, api::TimeCommandRequest> {
+    pub fn set(mut self, hour: u32, minute: u32, second: u32) -> Self {
+        self.req.hour = hour;
+        self.req.minute = minute;
+        self.req.second = second;
+        self
+    }
+}
+
+impl Command<'_, api::DateTimeCommandRequest> {
+    pub fn set(mut self, epoch_seconds: u32) -> Self {
+        self.req.epoch_seconds = epoch_seconds;
+        self
+    }
+}
+
+impl Command<'_, api::AlarmControlPanelCommandRequest> {
+    pub fn command(mut self, command: api::AlarmControlPanelStateCommand) -> Self {
+        self.req.command = command.into();
+        self
+    }
+
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.req.code = code.into();
+        self
+    }
+}
+
+impl Command<'_, api::UpdateCommandRequest> {
+    pub fn command(mut self, command: api::UpdateCommand) -> Self {
+        self.req.command = command.into();
+        self
+    }
+}
@@ -0,0 +1,433 @@
+use bytes::BytesMut;
+use prost::Message;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub mod command;
+pub mod state;
+
+pub use command::Command;
+pub use state::{EntityKey, EntityState};
+
+use crate::{
+    api,
+    connection::{
+        base::{Connection, Connectionable},
+        error::{ConnectionError, ErrorKind},
+    },
+    model::{ApiMessage, MessageType, Request},
+};
+
+/// How long a request waits for its response by default
+pub const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Max messages held back while waiting on a transaction
+pub const BACKLOG_CAPACITY: usize = 256;
+/// Default for [`Client::hello_timeout`]
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// API version we speak, sent in `HelloRequest`
+pub const CLIENT_API_VERSION: ApiVersion = ApiVersion::new(1, 12);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ApiVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("connection error `{0}`")]
+    ConnectionError(#[from] ConnectionError),
+    #[error("not connected")]
+    NotConnected,
+    #[error("already connected")]
+    AlreadyConnected,
+    #[error("device requested shutdown")]
+    DeviceRequestShutdown,
+    #[error("invalid password")]
+    InvalidPassword,
+    #[error("prost decode error `{0}`")]
+    ProstDecodeError(#[from] prost::DecodeError),
+    #[error("prost encode error `{0}`")]
+    ProstEncodeError(#[from] prost::EncodeError),
+    #[error("timed out waiting for `{0}`")]
+    TransactionTimeout(MessageType),
+    #[error("Hello exchange timed out after {0:?}")]
+    HelloTimeout(Duration),
+    #[error("ConnectRequest exchange timed out after {0:?}")]
+    ConnectRequestTimeout(Duration),
+    #[error("incompatible API version `{0}` (expected major version {major})", major = CLIENT_API_VERSION.major)]
+    IncompatibleApiVersion(ApiVersion),
+    #[error("system time error `{0}`")]
+    SystemTimeError(#[from] std::time::SystemTimeError),
+    #[error("system time error `{0}`")]
+    SystemTimeIntCastError(#[from] std::num::TryFromIntError),
+    #[error("`{0}` is only sent by the device")]
+    DeviceOnlyMessage(MessageType),
+}
+
+impl ClientError {
+    pub fn kind(&self) -> ErrorKind {
+        use ClientError::*;
+        match self {
+            ConnectionError(e) => e.kind(),
+            InvalidPassword => ErrorKind::InvalidPassword,
+            IncompatibleApiVersion(_) | ProstDecodeError(_) => ErrorKind::ProtocolMismatch,
+            TransactionTimeout(_) | HelloTimeout(_) | ConnectRequestTimeout(_) => {
+                ErrorKind::Timeout
+            }
+            NotConnected | DeviceRequestShutdown => ErrorKind::Disconnected,
+            ProstEncodeError(_)
+            | SystemTimeError(_)
+            | SystemTimeIntCastError(_)
+            | DeviceOnlyMessage(_)
+            | AlreadyConnected => ErrorKind::Internal,
+        }
+    }
+}
+
+/// A session with one ESPHome device: the Hello/Connect handshake,
+/// typed requests and answering the device's own requests (ping, time, disconnect).
+pub struct Client {
+    connection: Connection,
+    /// sent in `HelloRequest`, shows up in the device's logs
+    client_info: String,
+    password: String,
+    hello_timeout: Duration,
+    connected: bool,
+    /// messages received while waiting on a transaction
+    backlog: VecDeque<(MessageType, BytesMut)>,
+    /// negotiated in [`Client::connect`]
+    api_version: ApiVersion,
+    /// from `HelloResponse`, ex. "ESPHome v1.10.0 on ESP8266"
+    server_info: String,
+    /// last state of each entity by key, kept across reconnects
+    states: HashMap<EntityKey, EntityState>,
+}
+
+impl Client {
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            client_info: env!("CARGO_PKG_NAME").to_string(),
+            password: String::new(),
+            hello_timeout: HELLO_TIMEOUT,
+            connected: false,
+            backlog: VecDeque::new(),
+            api_version: ApiVersion::default(),
+            server_info: String::new(),
+            states: HashMap::new(),
+        }
+    }
+
+    /// Legacy API password, empty for none
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = password.into();
+        self
+    }
+
+    pub fn client_info(mut self, client_info: impl Into<String>) -> Self {
+        self.client_info = client_info.into();
+        self
+    }
+
+    /// How long each of the Hello and Connect exchanges may take
+    pub fn hello_timeout(mut self, timeout: Duration) -> Self {
+        self.hello_timeout = timeout;
+        self
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// What the device reported in its `HelloResponse`
+    pub fn api_version(&self) -> ApiVersion {
+        self.api_version
+    }
+
+    pub fn server_info(&self) -> &str {
+        &self.server_info
+    }
+
+    /// Take the version and server info from a `HelloResponse` that
+    /// didn't come through [`Client::connect`], ie. from a capture
+    pub fn set_hello(&mut self, hello: api::HelloResponse) {
+        self.api_version = ApiVersion::new(hello.api_version_major, hello.api_version_minor);
+        self.server_info = hello.server_info;
+    }
+
+    /// Last state the device reported for the entity, if any
+    pub fn state(&self, key: impl Into<EntityKey>) -> Option<&EntityState> {
+        self.states.get(&key.into())
+    }
+
+    /// Last state of every entity that reported one
+    pub fn states(&self) -> impl Iterator<Item = &EntityState> {
+        self.states.values()
+    }
+
+    /// Update the state cache if the message is an entity state.
+    /// Done for everything received, only needed by hand for
+    /// messages that came some other way.
+    pub fn update_state(&mut self, msg_type: &MessageType, bytes: &[u8]) {
+        match EntityState::decode(msg_type, bytes) {
+            Some(Ok(state)) => {
+                self.states.insert(state.entity_key(), state);
+            }
+            Some(Err(e)) => warn!(%msg_type, "not caching undecodable state: {e}"),
+            None => {}
+        }
+    }
+
+    /// Open the connection and log in
+    pub async fn connect(&mut self) -> Result<(), ClientError> {
+        if self.connected {
+            return Err(ClientError::AlreadyConnected);
+        }
+
+        self.connection.connect().await?;
+        self.backlog.clear();
+
+        // don't leave the socket open behind a failed login
+        if let Err(e) = self.login().await {
+            if let Err(close_err) = self.force_disconnect().await {
+                warn!("error closing connection after failed login: {close_err}");
+            }
+            return Err(e);
+        }
+
+        self.connected = true;
+        Ok(())
+    }
+
+    /// Hello and Connect on the freshly opened connection
+    async fn login(&mut self) -> Result<(), ClientError> {
+        let hello_timeout = self.hello_timeout;
+        let hello = self
+            .request_timeout(
+                &api::HelloRequest {
+                    client_info: self.client_info.clone(),
+                    api_version_major: CLIENT_API_VERSION.major,
+                    api_version_minor: CLIENT_API_VERSION.minor,
+                },
+                hello_timeout,
+            )
+            .await
+            .map_err(|e| match e {
+                ClientError::TransactionTimeout(_) => ClientError::HelloTimeout(hello_timeout),
+                e => e,
+            })?;
+
+        // major mismatch = incompatible base protocol, minor = older/newer messages
+        let api_version = ApiVersion::new(hello.api_version_major, hello.api_version_minor);
+        if api_version.major != CLIENT_API_VERSION.major {
+            return Err(ClientError::IncompatibleApiVersion(api_version));
+        }
+        if api_version.minor != CLIENT_API_VERSION.minor {
+            info!(%api_version, client = %CLIENT_API_VERSION, "API minor version differs");
+        }
+        self.api_version = api_version;
+        self.server_info = hello.server_info;
+
+        let res = self
+            .request_timeout(
+                &api::ConnectRequest {
+                    password: self.password.clone(),
+                },
+                hello_timeout,
+            )
+            .await
+            .map_err(|e| match e {
+                ClientError::TransactionTimeout(_) => {
+                    ClientError::ConnectRequestTimeout(hello_timeout)
+                }
+                e => e,
+            })?;
+
+        if res.invalid_password {
+            return Err(ClientError::InvalidPassword);
+        }
+
+        Ok(())
+    }
+
+    /// Send disconnect request to device, wait for response, then disconnect socket
+    pub async fn disconnect(&mut self) -> Result<(), ClientError> {
+        self.request(&api::DisconnectRequest {}).await?;
+        self.force_disconnect().await
+    }
+
+    /// Disconnect socket (without sending disconnect request to device)
+    pub async fn force_disconnect(&mut self) -> Result<(), ClientError> {
+        self.connected = false;
+        self.connection.disconnect().await?;
+        Ok(())
+    }
+
+    pub async fn send<M: ApiMessage>(&mut self, msg: &M) -> Result<(), ClientError> {
+        self.send_msg(M::MESSAGE_TYPE, msg).await
+    }
+
+    /// Send a message whose type isn't known statically
+    pub async fn send_msg(
+        &mut self,
+        msg_type: MessageType,
+        msg: &impl Message,
+    ) -> Result<(), ClientError> {
+        if !msg_type.from_client() {
+            return Err(ClientError::DeviceOnlyMessage(msg_type));
+        }
+        let msg_len = msg.encoded_len();
+        let mut bytes = BytesMut::with_capacity(msg_len);
+        msg.encode(&mut bytes)?;
+        bytes.truncate(msg_len);
+        self.connection.send_msg(msg_type, &bytes).await?;
+        Ok(())
+    }
+
+    /// Next raw message from the connection, nothing answered or held back
+    /// (states are still cached). Cancellation safe.
+    pub async fn recv_msg(&mut self) -> Result<(MessageType, BytesMut), ClientError> {
+        loop {
+            let (msg_type, msg) = self.connection.recv_msg().await?;
+            if !msg_type.from_device() {
+                warn!(%msg_type, "device sent a message only clients send, dropping it");
+                continue;
+            }
+            self.update_state(&msg_type, &msg);
+            return Ok((msg_type, msg));
+        }
+    }
+
+    /// Wait for a message of type `M`, handling anything else that
+    /// arrives in the meantime with [`Client::handle_unsolicited`]
+    pub async fn recv<M: ApiMessage>(&mut self) -> Result<M, ClientError> {
+        loop {
+            let (msg_type, mut msg) = self.recv_msg().await?;
+            if msg_type == M::MESSAGE_TYPE {
+                return Ok(M::decode(&mut msg)?);
+            }
+            self.handle_unsolicited(msg_type, msg).await?;
+        }
+    }
+
+    /// Send a request and wait up to [`TRANSACTION_TIMEOUT`] for its response
+    pub async fn request<R: Request>(&mut self, req: &R) -> Result<R::Response, ClientError> {
+        self.request_timeout(req, TRANSACTION_TIMEOUT).await
+    }
+
+    /// Send a request and wait up to `timeout` for its response
+    pub async fn request_timeout<R: Request>(
+        &mut self,
+        req: &R,
+        timeout: Duration,
+    ) -> Result<R::Response, ClientError> {
+        self.send(req).await?;
+        tokio::time::timeout(timeout, self.recv::<R::Response>())
+            .await
+            .map_err(|_| ClientError::TransactionTimeout(R::Response::MESSAGE_TYPE))?
+    }
+
+    /// Next message from the device, held back ones first,
+    /// answering its pings etc. on the way
+    pub async fn next_msg(&mut self) -> Result<(MessageType, BytesMut), ClientError> {
+        if let Some(held) = self.backlog.pop_front() {
+            return Ok(held);
+        }
+        loop {
+            let (msg_type, msg) = self.recv_msg().await?;
+            if !self.answer_request(&msg_type).await? {
+                return Ok((msg_type, msg));
+            }
+        }
+    }
+
+    /// Take the oldest message held back during a transaction
+    pub fn pop_backlog(&mut self) -> Option<(MessageType, BytesMut)> {
+        self.backlog.pop_front()
+    }
+
+    /// Deal with a message that arrived while waiting on something else.
+    /// Requests from the device are answered now, everything else is held
+    /// in the backlog until [`Client::pop_backlog`] or [`Client::next_msg`].
+    pub async fn handle_unsolicited(
+        &mut self,
+        msg_type: MessageType,
+        msg: BytesMut,
+    ) -> Result<(), ClientError> {
+        if self.answer_request(&msg_type).await? {
+            return Ok(());
+        }
+
+        if msg_type == MessageType::SubscribeLogsResponse {
+            return Ok(());
+        }
+
+        if self.backlog.len() >= BACKLOG_CAPACITY
+            && let Some((dropped, _)) = self.backlog.pop_front()
+        {
+            warn!(msg_type = %dropped, "backlog full, dropping message");
+        }
+        self.backlog.push_back((msg_type, msg));
+        Ok(())
+    }
+
+    /// Whether the client deals with `msg_type` itself: requests
+    /// [`Client::answer_request`] answers, and replies to its own handshake
+    pub fn handles(msg_type: &MessageType) -> bool {
+        matches!(
+            msg_type,
+            MessageType::DisconnectRequest
+                | MessageType::PingRequest
+                | MessageType::GetTimeRequest
+                | MessageType::HelloResponse
+                | MessageType::ConnectResponse
+                | MessageType::DisconnectResponse
+        )
+    }
+
+    /// Answer requests the device makes of us (ping, time, disconnect).
+    /// Returns whether `msg_type` was one of them.
+    pub async fn answer_request(&mut self, msg_type: &MessageType) -> Result<bool, ClientError> {
+        match msg_type {
+            MessageType::DisconnectRequest => {
+                self.send(&api::DisconnectResponse {}).await?;
+                self.force_disconnect().await?;
+                Err(ClientError::DeviceRequestShutdown)
+            }
+            MessageType::PingRequest => {
+                self.send(&api::PingResponse {}).await?;
+                Ok(true)
+            }
+            MessageType::GetTimeRequest => {
+                self.send(&api::GetTimeResponse {
+                    epoch_seconds: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)?
+                        .as_secs()
+                        .try_into()?,
+                })
+                .await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use prost::Message;
+
+use crate::{
+    api,
+    model::{EntityMessage, MessageType},
+};
+
+/// Addresses an entity. Keys are only unique within one (sub-)device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityKey {
+    /// ESPHome sub-device, 0 for the node itself
+    pub device_id: u32,
+    pub key: u32,
+}
+
+impl EntityKey {
+    pub const fn new(device_id: u32, key: u32) -> Self {
+        Self { device_id, key }
+    }
+}
+
+/// An entity on the node itself
+impl From<u32> for EntityKey {
+    fn from(key: u32) -> Self {
+        Self::new(0, key)
+    }
+}
+
+/// The state cache covers every entity type with state updates
+macro_rules! entity_states {
+    (
+        listed { $($listed:tt)* }
+        updates { $($(#[$cfg:meta])* $variant:ident => $msg:ident,)* }
+    ) => {
+        /// Last state a device reported for one of its entities
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum EntityState {
+            $($(#[$cfg])* $variant(api::$msg),)*
+        }
+
+        impl EntityState {
+            /// `None` if `msg_type` isn't an entity state
+            pub fn decode(
+                msg_type: &MessageType,
+                bytes: &[u8],
+            ) -> Option<Result<Self, prost::DecodeError>> {
+                Some(match msg_type {
+                    $(
+                        $(#[$cfg])*
+                        MessageType::$msg => api::$msg::decode(bytes).map(Self::$variant),
+                    )*
+                    _ => return None,
+                })
+            }
+
+            pub fn entity_key(&self) -> EntityKey {
+                match self {
+                    $($(#[$cfg])* Self::$variant(state) => state.entity_key(),)*
+                }
+            }
+        }
+    };
+}
+
+entity_messages!(entity_states);
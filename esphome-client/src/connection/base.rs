@@ -1,10 +1,13 @@
 use crate::{connection::error::ConnectionError, model::MessageType};
 use bytes::BytesMut;
+use std::sync::Arc;
 
-use super::{noise::NoiseConnection, plain::PlainConnection};
+use super::{capture::Recorder, noise::NoiseConnection, plain::PlainConnection};
 
 #[allow(async_fn_in_trait)]
 pub trait Connectionable {
+    /// Frame and write a message. Whether we may send it at all
+    /// ([`MessageType::source`]) is never checked here.
     async fn send_msg(
         &mut self,
         msg_type: MessageType,
@@ -14,7 +17,6 @@ pub trait Connectionable {
     async fn connect(&mut self) -> Result<(), ConnectionError>;
     async fn disconnect(&mut self) -> Result<(), ConnectionError>;
     fn get_name(&self) -> Option<String>;
-    async fn readable(&mut self) -> Result<(), ConnectionError>;
 }
 
 #[derive(Hash)]
@@ -35,6 +37,16 @@ impl From<PlainConnection> for Connection {
     }
 }
 
+impl Connection {
+    /// Capture every message from the next connect on
+    pub fn set_recorder(&mut self, recorder: Option<Arc<Recorder>>) {
+        match self {
+            Connection::Noise(con) => con.set_recorder(recorder),
+            Connection::Plain(con) => con.set_recorder(recorder),
+        }
+    }
+}
+
 impl Connectionable for Connection {
     #[inline]
     async fn send_msg(
@@ -79,12 +91,4 @@ impl Connectionable for Connection {
             Connection::Plain(con) => con.get_name(),
         }
     }
-
-    #[inline]
-    async fn readable(&mut self) -> Result<(), ConnectionError> {
-        match self {
-            Connection::Noise(con) => con.readable().await,
-            Connection::Plain(con) => con.readable().await,
-        }
-    }
 }
@@ -0,0 +1,120 @@
+use crate::model::{self, MessageType};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// from the device
+    Rx,
+    /// to the device
+    Tx,
+}
+
+/// One line of a capture file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// microseconds since the unix epoch
+    pub time_us: u64,
+    pub dir: Direction,
+    /// raw id, so the capture outlives changes to [`MessageType`]
+    pub msg_id: u16,
+    /// only for reading the file by hand
+    pub msg_type: String,
+    /// decrypted message body, base64. Passwords and keys are blanked out.
+    pub data: String,
+}
+
+impl CaptureRecord {
+    pub fn new(dir: Direction, msg_type: &MessageType, bytes: &[u8]) -> Self {
+        let time_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+        let redacted = model::redact(msg_type, bytes);
+        Self {
+            time_us,
+            dir,
+            msg_id: msg_type.clone() as u16,
+            msg_type: msg_type.to_string(),
+            data: BASE64_STANDARD.encode(redacted.as_deref().unwrap_or(bytes)),
+        }
+    }
+
+    /// `None` if the id is unknown to this build
+    pub fn msg_type(&self) -> Option<MessageType> {
+        MessageType::from_repr(self.msg_id)
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        BASE64_STANDARD.decode(&self.data)
+    }
+}
+
+/// Appends every message a connection sends or receives (after decryption)
+/// to a capture file, one JSON [`CaptureRecord`] per line.
+///
+/// Captures hold everything the device says in plaintext (only passwords and
+/// keys are blanked out), so the file is only readable by the current user.
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    /// opened on the first record
+    file: Mutex<Option<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Mutex::new(None),
+        }
+    }
+
+    pub async fn record(
+        &self,
+        dir: Direction,
+        msg_type: &MessageType,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&CaptureRecord::new(dir, msg_type, bytes))?;
+        line.push(b'\n');
+
+        let mut guard = self.file.lock().await;
+        let file = match guard.take() {
+            Some(file) => file,
+            None => {
+                let mut options = OpenOptions::new();
+                options.append(true).create(true);
+                #[cfg(unix)]
+                options.mode(0o600);
+                BufWriter::new(options.open(&self.path).await?)
+            }
+        };
+        let file = guard.insert(file);
+
+        file.write_all(&line).await?;
+        // a capture is usually wanted right after something broke
+        file.flush().await
+    }
+}
+
+/// Parse a capture file's content, line by line
+pub fn read_capture(
+    content: &str,
+) -> impl Iterator<Item = Result<CaptureRecord, serde_json::Error>> + '_ {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+}
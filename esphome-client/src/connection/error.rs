@@ -0,0 +1,92 @@
+use serde::Serialize;
+use std::{io, time::Duration};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConnectionError {
+    #[error("not connected")]
+    NotConnected,
+    #[error("TCP connect timed out after {0:?} (host unreachable?)")]
+    ConnectTimeout(Duration),
+    #[error("noise handshake timed out after {0:?}")]
+    HandshakeTimeout(Duration),
+    #[error("unknown message type `{0}`")]
+    UnknownMessageType(u16),
+    #[error("noise decrypt error `{0}`")]
+    NoiseDecrypt(#[from] snow::error::Error),
+    #[error("io error `{0}`")]
+    TcpIO(#[from] std::io::Error),
+    #[error("base64 decode slice error `{0}` (noise_psk may be incorrectly sized)")]
+    Base64DecodeSlice(#[from] base64::DecodeSliceError),
+    // the error would point at characters of the key
+    #[error("noise_psk is not valid base64")]
+    Base64Decode(#[from] base64::DecodeError),
+    #[error("missing noise_psk")]
+    MissingNoisePsk,
+    #[error("noise_psk must be 32 bytes, got {0}")]
+    WrongNoisePskLength(usize),
+    #[error("no randomness for a noise_psk `{0}`")]
+    Random(#[from] getrandom::Error),
+    #[error("client wants unknown noise protocol `{0}`")]
+    ClientWantsUnknownNoiseProtocol(u8),
+    #[error("recieved message missing null terminator")]
+    MessageMissingNullTerminator,
+    #[error("handshake had wrong preamble `{0}`")]
+    HandshakeHadWrongPreamble(u8),
+    #[error("frame had wrong preamble `{0}` (may have wrong Connection type)")]
+    FrameHadWrongPreamble(u8),
+    #[error("frame too short for a message header ({0} bytes)")]
+    FrameTooShort(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Rough cause of a failure, for reporting
+pub enum ErrorKind {
+    /// bad command payload, key, entity, etc.
+    InvalidParams,
+    /// nothing answering at the address
+    Unreachable,
+    /// noise PSK missing, wrong or rejected
+    WrongPsk,
+    InvalidPassword,
+    /// encryption mismatch, incompatible API version, malformed messages
+    ProtocolMismatch,
+    /// device connected, but stopped answering
+    Timeout,
+    /// connection dropped
+    Disconnected,
+    /// a bug, or the extension itself failing
+    Internal,
+}
+
+impl ConnectionError {
+    pub fn kind(&self) -> ErrorKind {
+        use ConnectionError::*;
+        match self {
+            NotConnected => ErrorKind::Disconnected,
+            ConnectTimeout(_) => ErrorKind::Unreachable,
+            HandshakeTimeout(_) => ErrorKind::Timeout,
+            TcpIO(e) => match e.kind() {
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::HostUnreachable
+                | io::ErrorKind::NetworkUnreachable
+                | io::ErrorKind::AddrNotAvailable
+                | io::ErrorKind::TimedOut => ErrorKind::Unreachable,
+                _ => ErrorKind::Disconnected,
+            },
+            // ESPHome answers a bad PSK with an error frame instead of a handshake
+            NoiseDecrypt(_) | HandshakeHadWrongPreamble(_) => ErrorKind::WrongPsk,
+            Base64DecodeSlice(_) | Base64Decode(_) | MissingNoisePsk | WrongNoisePskLength(_) => {
+                ErrorKind::InvalidParams
+            }
+            // ie. plaintext to a device that wants encryption, or the other way around
+            FrameHadWrongPreamble(_)
+            | ClientWantsUnknownNoiseProtocol(_)
+            | MessageMissingNullTerminator
+            | FrameTooShort(_)
+            | UnknownMessageType(_) => ErrorKind::ProtocolMismatch,
+            Random(_) => ErrorKind::Internal,
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod base;
+pub mod capture;
 pub mod error;
 pub mod noise;
 pub mod plain;
+pub mod split;
+pub mod timeouts;
 pub mod varu;
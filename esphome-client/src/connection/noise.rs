@@ -1,20 +1,30 @@
-use super::base::Connectionable;
+use super::{
+    base::Connectionable,
+    capture::Recorder,
+    split::{MsgReader, MsgWriter, Received, SplitConnection},
+    timeouts::Timeouts,
+};
 use crate::{connection::error::ConnectionError, model::MessageType};
 use base64::prelude::*;
 use bytes::{Buf, BytesMut};
 use memchr::memchr;
-use snow::{HandshakeState, TransportState};
+use snow::{HandshakeState, StatelessTransportState};
 use std::{
     hash::{Hash, Hasher},
+    sync::Arc,
     time::Duration,
 };
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufWriter},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    time::timeout,
 };
 
 pub const NOISE_HELLO: &[u8; 3] = b"\x01\x00\x00";
-pub const READ_TIMEOUT: Option<Duration> = Some(Duration::from_secs(60));
+pub const READ_TIMEOUT: Duration = Duration::from_secs(60);
 pub const NOISE_PARAMS: &str = "Noise_NNpsk0_25519_ChaChaPoly_SHA256";
 pub const NOISE_PROLOGUE: &[u8; 14] = b"NoiseAPIInit\x00\x00";
 pub const NOISE_PSK_LEN: usize = 32;
@@ -22,8 +32,9 @@ pub const NOISE_PSK_LEN: usize = 32;
 pub struct NoiseConnection {
     pub(crate) ip: String,
     noise_psk: String,
-    pub(crate) stream: Option<TcpStream>,
-    noise: Option<TransportState>,
+    timeouts: Timeouts,
+    split: Option<SplitConnection>,
+    recorder: Option<Arc<Recorder>>,
     pub server_name: Option<String>,
 }
 
@@ -33,15 +44,45 @@ impl Hash for NoiseConnection {
     }
 }
 
-impl Connectionable for NoiseConnection {
-    async fn send_msg(
+/// Both halves share the transport state, each tracks its own nonce
+struct NoiseReader {
+    stream: OwnedReadHalf,
+    noise: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+struct NoiseWriter {
+    stream: BufWriter<OwnedWriteHalf>,
+    noise: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl MsgReader for NoiseReader {
+    async fn read_msg(&mut self) -> Received {
+        let frame = NoiseConnection::read_frame(&mut self.stream).await?;
+        let mut msg = BytesMut::with_capacity(65535);
+        msg.resize(65535, 0);
+        let msg_size = self.noise.read_message(self.nonce, &frame, &mut msg)?;
+        self.nonce += 1;
+        msg.truncate(msg_size);
+        // type and length, both u16
+        if msg.len() < 4 {
+            return Err(ConnectionError::FrameTooShort(msg.len()));
+        }
+        let msg_type_num = u16::from_be_bytes([msg[0], msg[1]]);
+        let msg_type = MessageType::from_repr(msg_type_num)
+            .ok_or(ConnectionError::UnknownMessageType(msg_type_num))?;
+        msg.advance(4);
+        Ok((msg_type, msg))
+    }
+}
+
+impl MsgWriter for NoiseWriter {
+    async fn write_msg(
         &mut self,
         msg_type: MessageType,
-        msg_bytes: &BytesMut,
+        msg_bytes: BytesMut,
     ) -> Result<(), ConnectionError> {
-        let stream = self.stream.as_mut().ok_or(ConnectionError::NotConnected)?;
-        let noise = self.noise.as_mut().ok_or(ConnectionError::NotConnected)?;
-
         //make frame
         let msg_type = msg_type as usize;
         let msg_len = msg_bytes.len();
@@ -54,12 +95,13 @@ impl Connectionable for NoiseConnection {
         //TODO reuse buffer?
         let mut frame = BytesMut::with_capacity(frame_header.len() + msg_len);
         frame.extend_from_slice(&frame_header);
-        frame.extend_from_slice(msg_bytes);
+        frame.extend_from_slice(&msg_bytes);
 
         //encrypt frame
         let mut eframe = BytesMut::with_capacity(65535);
         eframe.resize(65535, 0);
-        let eframe_len = noise.write_message(&frame, &mut eframe)?;
+        let eframe_len = self.noise.write_message(self.nonce, &frame, &mut eframe)?;
+        self.nonce += 1;
         eframe.truncate(eframe_len);
 
         //make packet
@@ -70,52 +112,79 @@ impl Connectionable for NoiseConnection {
         packet.extend_from_slice(&eframe);
 
         //send packet
-        stream.write_all(&packet).await?;
-        stream.flush().await?;
+        self.stream.write_all(&packet).await?;
 
         Ok(())
     }
 
-    async fn recv_msg(&mut self) -> Result<(MessageType, BytesMut), ConnectionError> {
-        let stream = self.stream.as_mut().ok_or(ConnectionError::NotConnected)?;
-        let noise = self.noise.as_mut().ok_or(ConnectionError::NotConnected)?;
-        let frame = Self::read_frame(stream).await?;
-        let mut msg = BytesMut::with_capacity(65535);
-        msg.resize(65535, 0);
-        let msg_size = noise.read_message(&frame, &mut msg)?;
-        msg.truncate(msg_size);
-        let msg_type_num = u16::from_be_bytes([msg[0], msg[1]]);
-        let msg_type = MessageType::from_repr(msg_type_num)
-            .ok_or(ConnectionError::UnknownMessageType(msg_type_num))?;
-        msg.advance(4);
-        Ok((msg_type, msg))
+    async fn flush(&mut self) -> Result<(), ConnectionError> {
+        self.stream.flush().await?;
+        Ok(())
     }
 
-    async fn readable(&mut self) -> Result<(), ConnectionError> {
-        let stream = self.stream.as_mut().ok_or(ConnectionError::NotConnected)?;
-        stream.readable().await?;
+    async fn shutdown(&mut self) -> Result<(), ConnectionError> {
+        self.stream.shutdown().await?;
         Ok(())
     }
+}
+
+impl Connectionable for NoiseConnection {
+    async fn send_msg(
+        &mut self,
+        msg_type: MessageType,
+        msg_bytes: &BytesMut,
+    ) -> Result<(), ConnectionError> {
+        let split = self.split.as_ref().ok_or(ConnectionError::NotConnected)?;
+        split.send(msg_type, msg_bytes).await
+    }
+
+    async fn recv_msg(&mut self) -> Result<(MessageType, BytesMut), ConnectionError> {
+        let split = self.split.as_mut().ok_or(ConnectionError::NotConnected)?;
+        split.recv().await
+    }
 
     async fn connect(&mut self) -> Result<(), ConnectionError> {
-        if self.stream.is_some() {
+        if self.split.is_some() {
             return Ok(()); //TODO: is this wanted behavior... should this error? should it reconnect?
         }
         let mut noise_handshake = Self::setup_noise(&self.noise_psk)?;
-        let mut stream = TcpStream::connect(&self.ip).await?;
-        Self::send_hello(&mut stream, &mut noise_handshake).await?;
-        self.server_name = Some(Self::receive_hello(&mut stream).await?);
-        self.noise = Some(Self::receive_handshake(&mut stream, noise_handshake).await?);
-        self.stream = Some(stream);
+        let mut stream = timeout(self.timeouts.connect, TcpStream::connect(&self.ip))
+            .await
+            .map_err(|_| ConnectionError::ConnectTimeout(self.timeouts.connect))??;
+        // batching is up to the writer task
+        stream.set_nodelay(true)?;
+        let (server_name, noise) = timeout(self.timeouts.handshake, async {
+            Self::send_hello(&mut stream, &mut noise_handshake).await?;
+            let server_name = Self::receive_hello(&mut stream).await?;
+            let noise = Self::receive_handshake(&mut stream, noise_handshake).await?;
+            Ok::<_, ConnectionError>((server_name, noise))
+        })
+        .await
+        .map_err(|_| ConnectionError::HandshakeTimeout(self.timeouts.handshake))??;
+        self.server_name = Some(server_name);
+
+        let noise = Arc::new(noise);
+        let (read, write) = stream.into_split();
+        self.split = Some(SplitConnection::spawn(
+            NoiseReader {
+                stream: read,
+                noise: noise.clone(),
+                nonce: 0,
+            },
+            NoiseWriter {
+                stream: BufWriter::new(write),
+                noise,
+                nonce: 0,
+            },
+            self.recorder.clone(),
+        ));
         Ok(())
     }
 
     async fn disconnect(&mut self) -> Result<(), ConnectionError> {
-        if let Some(stream) = &mut self.stream {
-            stream.shutdown().await?;
+        if let Some(split) = self.split.take() {
+            split.close().await;
         }
-        self.noise = None;
-        self.stream = None;
         self.server_name = None;
         Ok(())
     }
@@ -125,20 +194,39 @@ impl Connectionable for NoiseConnection {
     }
 }
 
+/// Decode a base64 noise PSK, checking it's exactly [`NOISE_PSK_LEN`] bytes
+pub fn decode_noise_psk(noise_psk: &str) -> Result<[u8; NOISE_PSK_LEN], ConnectionError> {
+    let key = BASE64_STANDARD.decode(noise_psk)?;
+    key.try_into()
+        .map_err(|key: Vec<u8>| ConnectionError::WrongNoisePskLength(key.len()))
+}
+
+/// Generate a random base64 noise PSK
+pub fn generate_noise_psk() -> Result<String, ConnectionError> {
+    let mut key = [0u8; NOISE_PSK_LEN];
+    getrandom::getrandom(&mut key)?;
+    Ok(BASE64_STANDARD.encode(key))
+}
+
 impl NoiseConnection {
-    pub fn new(ip: String, noise_psk: String) -> Self {
+    pub fn new(ip: String, noise_psk: String, timeouts: Timeouts) -> Self {
         Self {
             ip,
             noise_psk,
-            stream: None,
-            noise: None,
+            timeouts,
+            split: None,
+            recorder: None,
             server_name: None,
         }
     }
 
+    /// Capture every message from the next connect on
+    pub fn set_recorder(&mut self, recorder: Option<Arc<Recorder>>) {
+        self.recorder = recorder;
+    }
+
     fn setup_noise(noise_psk: &str) -> Result<HandshakeState, ConnectionError> {
-        let mut key = [0u8; 32];
-        BASE64_STANDARD.decode_slice(noise_psk, &mut key)?;
+        let key = decode_noise_psk(noise_psk)?;
         Ok(snow::Builder::new(NOISE_PARAMS.parse()?)
             .psk(0, &key)?
             .prologue(NOISE_PROLOGUE)?
@@ -179,16 +267,18 @@ impl NoiseConnection {
     async fn receive_handshake(
         stream: &mut TcpStream,
         mut noise_handshake: HandshakeState,
-    ) -> Result<TransportState, ConnectionError> {
+    ) -> Result<StatelessTransportState, ConnectionError> {
         let frame = Self::read_frame(stream).await?;
         if frame[0] != 0x00 {
             return Err(ConnectionError::HandshakeHadWrongPreamble(frame[0]));
         }
         noise_handshake.read_message(&frame[1..], &mut [])?;
-        Ok(noise_handshake.into_transport_mode()?)
+        Ok(noise_handshake.into_stateless_transport_mode()?)
     }
 
-    async fn read_frame(stream: &mut TcpStream) -> Result<BytesMut, ConnectionError> {
+    async fn read_frame(
+        stream: &mut (impl AsyncRead + Unpin),
+    ) -> Result<BytesMut, ConnectionError> {
         let mut header = [0u8; 3];
         stream.read_exact(&mut header).await?;
         if header[0] != 0x01 {
@@ -196,9 +286,8 @@ impl NoiseConnection {
         }
         let frame_size = u16::from_be_bytes([header[1], header[2]]) as usize;
 
-        let mut frame = BytesMut::with_capacity(frame_size);
-        let size = stream.read_buf(&mut frame).await?;
-        frame.truncate(size);
+        let mut frame = BytesMut::zeroed(frame_size);
+        stream.read_exact(&mut frame).await?;
         Ok(frame)
     }
 }
@@ -0,0 +1,148 @@
+use super::base::Connectionable;
+use super::capture::Recorder;
+use super::split::{MsgReader, MsgWriter, Received, SplitConnection};
+use super::timeouts::Timeouts;
+use super::varu::{Varu32, varu32_to_bytes};
+use crate::connection::error::ConnectionError;
+use crate::model::MessageType;
+use bytes::{BufMut, BytesMut};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout};
+
+///NOTE UNTESTED!!!!!!!!
+pub struct PlainConnection {
+    pub(crate) ip: String,
+    timeouts: Timeouts,
+    split: Option<SplitConnection>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl Hash for PlainConnection {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ip.hash(state);
+    }
+}
+
+struct PlainReader {
+    stream: OwnedReadHalf,
+}
+
+struct PlainWriter {
+    stream: BufWriter<OwnedWriteHalf>,
+}
+
+impl MsgReader for PlainReader {
+    async fn read_msg(&mut self) -> Received {
+        let stream = &mut self.stream;
+        let preamble = stream.read_varu32().await?;
+        if preamble != 0x00 {
+            return Err(ConnectionError::FrameHadWrongPreamble(preamble as u8));
+        }
+
+        let msg_len = stream.read_varu32().await? as usize;
+        let msg_type_num = stream.read_varu32().await? as u16;
+        let msg_type = MessageType::from_repr(msg_type_num)
+            .ok_or(ConnectionError::UnknownMessageType(msg_type_num))?;
+        let mut msg = BytesMut::zeroed(msg_len);
+        stream.read_exact(&mut msg).await?;
+        Ok((msg_type, msg))
+    }
+}
+
+impl MsgWriter for PlainWriter {
+    async fn write_msg(
+        &mut self,
+        msg_type: MessageType,
+        msg_bytes: BytesMut,
+    ) -> Result<(), ConnectionError> {
+        let msg_type_var = varu32_to_bytes(msg_type as u32);
+        let msg_len = msg_bytes.len();
+        let msg_len_var = varu32_to_bytes(msg_len as u32);
+
+        let mut packet =
+            BytesMut::with_capacity(msg_len + 1 + msg_type_var.len() + msg_len_var.len());
+        packet.put_u8(0);
+        packet.extend_from_slice(&msg_len_var);
+        packet.extend_from_slice(&msg_type_var);
+        packet.extend_from_slice(&msg_bytes);
+
+        self.stream.write_all(&packet).await?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), ConnectionError> {
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), ConnectionError> {
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+impl Connectionable for PlainConnection {
+    async fn send_msg(
+        &mut self,
+        msg_type: MessageType,
+        msg_bytes: &BytesMut,
+    ) -> Result<(), ConnectionError> {
+        let split = self.split.as_ref().ok_or(ConnectionError::NotConnected)?;
+        split.send(msg_type, msg_bytes).await
+    }
+
+    async fn recv_msg(&mut self) -> Result<(MessageType, BytesMut), ConnectionError> {
+        let split = self.split.as_mut().ok_or(ConnectionError::NotConnected)?;
+        split.recv().await
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        if self.split.is_some() {
+            return Ok(()); //TODO: is this wanted behavior... should this error? should it reconnect?
+        }
+        let stream = timeout(self.timeouts.connect, TcpStream::connect(&self.ip))
+            .await
+            .map_err(|_| ConnectionError::ConnectTimeout(self.timeouts.connect))??;
+        // batching is up to the writer task
+        stream.set_nodelay(true)?;
+        let (read, write) = stream.into_split();
+        self.split = Some(SplitConnection::spawn(
+            PlainReader { stream: read },
+            PlainWriter {
+                stream: BufWriter::new(write),
+            },
+            self.recorder.clone(),
+        ));
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        if let Some(split) = self.split.take() {
+            split.close().await;
+        }
+        Ok(())
+    }
+
+    fn get_name(&self) -> Option<String> {
+        None
+    }
+}
+
+impl PlainConnection {
+    pub fn new(ip: String, timeouts: Timeouts) -> Self {
+        Self {
+            ip,
+            timeouts,
+            split: None,
+            recorder: None,
+        }
+    }
+
+    /// Capture every message from the next connect on
+    pub fn set_recorder(&mut self, recorder: Option<Arc<Recorder>>) {
+        self.recorder = recorder;
+    }
+}
@@ -0,0 +1,162 @@
+use super::{
+    capture::{Direction, Recorder},
+    error::ConnectionError,
+};
+use crate::model::MessageType;
+use bytes::BytesMut;
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::mpsc, task::JoinHandle, time::timeout};
+use tracing::{Instrument, trace, warn};
+
+/// Messages buffered between the device and the reader/writer tasks
+pub const CHANNEL_CAPACITY: usize = 64;
+
+/// How long [`SplitConnection::close`] waits for queued writes to go out
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Received = Result<(MessageType, BytesMut), ConnectionError>;
+
+/// Read half of a connection, owned by the reader task
+pub trait MsgReader: Send + 'static {
+    fn read_msg(&mut self) -> impl Future<Output = Received> + Send;
+}
+
+/// Write half of a connection, owned by the writer task.
+/// Writes may be buffered until [`MsgWriter::flush`].
+pub trait MsgWriter: Send + 'static {
+    fn write_msg(
+        &mut self,
+        msg_type: MessageType,
+        msg_bytes: BytesMut,
+    ) -> impl Future<Output = Result<(), ConnectionError>> + Send;
+    fn flush(&mut self) -> impl Future<Output = Result<(), ConnectionError>> + Send;
+    fn shutdown(&mut self) -> impl Future<Output = Result<(), ConnectionError>> + Send;
+}
+
+/// A connected stream split into a reader task and a writer task.
+///
+/// Frames are only ever read by the reader task, so dropping a
+/// [`SplitConnection::recv`] future (ie. in `select!`) never loses
+/// part of a frame or desyncs the noise nonces.
+///
+/// Messages queued back to back are batched into one write, except
+/// [`MessageType::no_delay`] ones which are flushed right away.
+pub struct SplitConnection {
+    /// `None` once closing
+    write_tx: Option<mpsc::Sender<(MessageType, BytesMut)>>,
+    read_rx: mpsc::Receiver<Received>,
+    reader: JoinHandle<()>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl SplitConnection {
+    /// `recorder` captures every message going either way
+    pub fn spawn(
+        mut reader: impl MsgReader,
+        mut writer: impl MsgWriter,
+        recorder: Option<Arc<Recorder>>,
+    ) -> Self {
+        let (write_tx, mut write_rx) = mpsc::channel::<(MessageType, BytesMut)>(CHANNEL_CAPACITY);
+        let (read_tx, read_rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+        let writer_err_tx = read_tx.clone();
+        let writer_recorder = recorder.clone();
+        let writer = tokio::spawn(
+            async move {
+                while let Some((msg_type, msg_bytes)) = write_rx.recv().await {
+                    trace!(%msg_type, len = msg_bytes.len(), "send");
+                    if let Some(recorder) = &writer_recorder {
+                        record(recorder, Direction::Tx, &msg_type, &msg_bytes).await;
+                    }
+                    let flush = msg_type.no_delay() || write_rx.is_empty();
+                    let mut res = writer.write_msg(msg_type, msg_bytes).await;
+                    if res.is_ok() && flush {
+                        res = writer.flush().await;
+                    }
+                    if let Err(e) = res {
+                        // surface it where the device is listening
+                        let _ = writer_err_tx.send(Err(e)).await;
+                        return;
+                    }
+                }
+                // every sender dropped -> disconnecting
+                let _ = writer.shutdown().await;
+            }
+            .in_current_span(),
+        );
+
+        let reader = tokio::spawn(
+            async move {
+                loop {
+                    let res = reader.read_msg().await;
+                    if let Ok((msg_type, msg_bytes)) = &res {
+                        trace!(%msg_type, len = msg_bytes.len(), "recv");
+                        if let Some(recorder) = &recorder {
+                            record(recorder, Direction::Rx, msg_type, msg_bytes).await;
+                        }
+                    }
+                    let failed = res.is_err();
+                    if read_tx.send(res).await.is_err() || failed {
+                        return;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+
+        Self {
+            write_tx: Some(write_tx),
+            read_rx,
+            reader,
+            writer: Some(writer),
+        }
+    }
+
+    /// Queue a message for the writer task
+    pub async fn send(
+        &self,
+        msg_type: MessageType,
+        msg_bytes: &BytesMut,
+    ) -> Result<(), ConnectionError> {
+        self.write_tx
+            .as_ref()
+            .ok_or(ConnectionError::NotConnected)?
+            .send((msg_type, msg_bytes.clone()))
+            .await
+            .map_err(|_| ConnectionError::NotConnected)
+    }
+
+    /// Next message from the reader task. Cancellation safe.
+    pub async fn recv(&mut self) -> Received {
+        self.read_rx
+            .recv()
+            .await
+            .unwrap_or(Err(ConnectionError::NotConnected))
+    }
+
+    /// Flush queued writes, shut the stream down and stop both tasks.
+    /// Gives up on the writes after [`CLOSE_TIMEOUT`].
+    pub async fn close(mut self) {
+        self.write_tx = None;
+        let Some(mut writer) = self.writer.take() else {
+            return;
+        };
+        if timeout(CLOSE_TIMEOUT, &mut writer).await.is_err() {
+            warn!("writer didn't finish within {CLOSE_TIMEOUT:?}, aborting it");
+            writer.abort();
+        }
+    }
+}
+
+impl Drop for SplitConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// A failing capture shouldn't take the connection down with it
+async fn record(recorder: &Recorder, dir: Direction, msg_type: &MessageType, bytes: &[u8]) {
+    if let Err(e) = recorder.record(dir, msg_type, bytes).await {
+        warn!("error writing capture: {e}");
+    }
+}
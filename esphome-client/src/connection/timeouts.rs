@@ -0,0 +1,41 @@
+use super::noise::READ_TIMEOUT;
+use serde::{Deserialize, Serialize};
+use serde_with::{DurationSeconds, serde_as};
+use std::time::Duration;
+
+/// How long each connection phase may take before the device is
+/// considered hung. Configurable per device, in seconds.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// TCP connect
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub connect: Duration,
+    /// noise hello + handshake
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub handshake: Duration,
+    /// each of the Hello and Connect exchanges
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub hello: Duration,
+    /// from ListEntitiesRequest to ListEntitiesDoneResponse
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub list_entities: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            handshake: READ_TIMEOUT,
+            hello: Duration::from_secs(10),
+            list_entities: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
@@ -1,9 +1,6 @@
 use async_trait::async_trait;
 use bytes::{BufMut, BytesMut};
-use tokio::{
-    io::{self, AsyncReadExt},
-    net::TcpStream,
-};
+use tokio::io::{self, AsyncRead, AsyncReadExt};
 
 #[async_trait]
 pub trait Varu32: AsyncReadExt {
@@ -11,7 +8,7 @@ pub trait Varu32: AsyncReadExt {
 }
 
 #[async_trait]
-impl Varu32 for TcpStream {
+impl<T: AsyncRead + Unpin + Send> Varu32 for T {
     /// [Docs](https://sqlite.org/src4/doc/trunk/www/varint.wiki)
     async fn read_varu32(&mut self) -> io::Result<u32> {
         let first_byte = self.read_u8().await?;
@@ -0,0 +1,13 @@
+//! ESPHome native API client: connections (plaintext and noise), the
+//! Hello/Connect handshake, typed requests and entity state tracking.
+
+// first, so `client` can use the macros it generates
+#[macro_use]
+pub mod model {
+    include!(concat!(env!("OUT_DIR"), "/model.rs"));
+}
+pub mod api {
+    include!(concat!(env!("OUT_DIR"), "/_.rs"));
+}
+pub mod client;
+pub mod connection;
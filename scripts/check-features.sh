@@ -0,0 +1,8 @@
+#!/bin/sh
+# Lint every feature combination of both crates. Needs cargo-hack:
+#   cargo install cargo-hack
+set -e
+cd "$(dirname "$0")/.."
+cargo hack clippy --feature-powerset --all-targets -p esphome-client -- -D warnings
+# `full` is only the sum of the others
+cargo hack clippy --feature-powerset --exclude-features full --all-targets -p igloo-esphome -- -D warnings
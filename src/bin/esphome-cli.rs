@@ -0,0 +1,476 @@
+//! Probe and control ESPHome devices without Igloo, ie. to check a node's
+//! credentials and entities before adopting it.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use igloo_esphome::{
+    api,
+    connection::noise,
+    custom::{self, EntityMetadata},
+    device::{ConnectionParams, Device, DeviceControl, DeviceError},
+    logging,
+    model::MessageType,
+    secret::Secret,
+};
+use igloo_interface::{Component, ipc::ExtensionToIgloo};
+use prost::Message;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    process::exit,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::oneshot,
+    time::{sleep, timeout},
+};
+
+/// ESPHome's native API port
+const DEFAULT_PORT: u16 = 6053;
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `call` waits for the device to come up
+const SETUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long `call` keeps printing states after writing
+const CALL_ECHO: Duration = Duration::from_secs(2);
+
+#[derive(Parser)]
+#[command(name = "esphome-cli", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Target {
+    /// `host` or `host:port`
+    ip: String,
+    /// Noise PSK (base64), leave out for plaintext devices
+    #[arg(long)]
+    psk: Option<String>,
+    /// API password (plaintext devices only)
+    #[arg(long)]
+    password: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Connect and print device info
+    Info(Target),
+    /// List entities by the IDs Igloo would give them
+    Entities(Target),
+    /// Stream state changes until Ctrl-C
+    Watch(Target),
+    /// Stream the device's logs until Ctrl-C
+    Logs {
+        #[command(flatten)]
+        target: Target,
+        #[arg(long, value_enum, default_value_t = LogLevel::Debug)]
+        level: LogLevel,
+        /// Have the device log its config first
+        #[arg(long)]
+        dump_config: bool,
+    },
+    /// Write a value to an entity
+    Call {
+        #[command(flatten)]
+        target: Target,
+        /// Entity ID, as listed by `entities`
+        entity: String,
+        /// `on`, `off`, a number, text or components as JSON.
+        /// Leave out to press a button.
+        value: Option<String>,
+    },
+    /// Measure round trip times
+    Ping {
+        #[command(flatten)]
+        target: Target,
+        #[arg(short, long, default_value_t = 4)]
+        count: u32,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Config,
+    Debug,
+    Verbose,
+    VeryVerbose,
+}
+
+impl From<LogLevel> for api::LogLevel {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => api::LogLevel::Error,
+            LogLevel::Warn => api::LogLevel::Warn,
+            LogLevel::Info => api::LogLevel::Info,
+            LogLevel::Config => api::LogLevel::Config,
+            LogLevel::Debug => api::LogLevel::Debug,
+            LogLevel::Verbose => api::LogLevel::Verbose,
+            LogLevel::VeryVerbose => api::LogLevel::VeryVerbose,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    // only our own problems, the output is what was asked for
+    let log_filter = logging::init();
+    let _ = log_filter.apply(Some("warn"), []);
+
+    let res = match cli.command {
+        Command::Info(target) => info(target).await,
+        Command::Entities(target) => entities(target).await,
+        Command::Watch(target) => watch(target).await,
+        Command::Logs {
+            target,
+            level,
+            dump_config,
+        } => logs(target, level, dump_config).await,
+        Command::Call {
+            target,
+            entity,
+            value,
+        } => call(target, entity, value).await,
+        Command::Ping { target, count } => ping(target, count).await,
+    };
+
+    if let Err(e) = res {
+        eprintln!("error: {e}");
+        exit(1);
+    }
+}
+
+fn device(target: &Target) -> Result<Device, Box<dyn Error>> {
+    // fail on a bad key before connecting
+    if let Some(psk) = &target.psk {
+        noise::decode_noise_psk(psk)?;
+    }
+
+    let ip = if target.ip.contains(':') {
+        target.ip.clone()
+    } else {
+        format!("{}:{DEFAULT_PORT}", target.ip)
+    };
+    let params = ConnectionParams {
+        ip,
+        noise_psk: target.psk.clone().map(Secret::new),
+        password: target.password.clone().map(Secret::new),
+        ..Default::default()
+    };
+    Ok(Device::new(0, params))
+}
+
+async fn info(target: Target) -> Result<(), Box<dyn Error>> {
+    let mut device = device(&target)?;
+    let info = device.connect().await?;
+    let _ = device.disconnect().await;
+
+    let encryption = match (&target.psk, info.api_encryption_supported) {
+        (Some(_), _) => "noise",
+        (None, true) => "none (supported)",
+        (None, false) => "none",
+    };
+    let rows = [
+        ("name", info.name.as_str()),
+        ("friendly name", &info.friendly_name),
+        ("mac", &info.mac_address),
+        ("model", &info.model),
+        ("esphome", &info.esphome_version),
+        ("compiled", &info.compilation_time),
+        ("project", &info.project_name),
+        ("project version", &info.project_version),
+        ("area", custom::area(&info)),
+        ("server", device.server_info()),
+        ("encryption", encryption),
+    ];
+    for (label, value) in rows {
+        if !value.is_empty() {
+            println!("{label:<16} {value}");
+        }
+    }
+    println!("{:<16} {}", "api", device.api_version());
+    for sub in &info.devices {
+        match custom::area_name(&info, sub.area_id) {
+            Some(area) => println!(
+                "{:<16} {} ({}, in {area})",
+                "sub-device", sub.name, sub.device_id
+            ),
+            None => println!("{:<16} {} ({})", "sub-device", sub.name, sub.device_id),
+        }
+    }
+    Ok(())
+}
+
+async fn entities(target: Target) -> Result<(), Box<dyn Error>> {
+    let mut device = device(&target)?;
+    let (igloo_tx, igloo_rx) = kanal::unbounded_async();
+
+    device.connect().await?;
+    device.register_entities(&igloo_tx).await?;
+    let _ = device.disconnect().await;
+
+    let mut entities = Entities::default();
+    while let Ok(Some(msg)) = igloo_rx.try_recv() {
+        entities.handle(msg);
+    }
+
+    for entity in entities.by_index.values() {
+        println!("{:<40} {:<30} {:?}", entity.id, entity.name, entity.comps);
+    }
+    Ok(())
+}
+
+async fn watch(target: Target) -> Result<(), Box<dyn Error>> {
+    let running = Running::spawn(device(&target)?);
+    let mut entities = Entities::default();
+
+    loop {
+        tokio::select! {
+            msg = running.igloo_rx.recv() => {
+                let Ok(msg) = msg else {
+                    break;
+                };
+                if let Some((index, comps)) = entities.handle(msg) {
+                    println!("{:<40} {comps:?}", entities.id(index));
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    running.shutdown().await;
+    Ok(())
+}
+
+async fn logs(target: Target, level: LogLevel, dump_config: bool) -> Result<(), Box<dyn Error>> {
+    let mut device = device(&target)?;
+    device.connect().await?;
+    device
+        .send_msg(
+            MessageType::SubscribeLogsRequest,
+            &api::SubscribeLogsRequest {
+                level: api::LogLevel::from(level).into(),
+                dump_config,
+            },
+        )
+        .await?;
+
+    loop {
+        tokio::select! {
+            res = device.next_msg() => {
+                let (msg_type, msg) = res?;
+                if msg_type == MessageType::SubscribeLogsResponse {
+                    let log = api::SubscribeLogsResponse::decode(msg)?;
+                    println!("{}", String::from_utf8_lossy(&log.message));
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let _ = device.disconnect().await;
+    Ok(())
+}
+
+async fn call(
+    target: Target,
+    entity_id: String,
+    value: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let comps = parse_value(value.as_deref())?;
+    let running = Running::spawn(device(&target)?);
+    let mut entities = Entities::default();
+
+    // registered = connected, and writes are taken from here on
+    let index = timeout(SETUP_TIMEOUT, async {
+        loop {
+            let msg = running.igloo_rx.recv().await?;
+            entities.handle(msg);
+            if let Some(index) = entities.index_of(&entity_id) {
+                return Ok::<_, kanal::ReceiveError>(index);
+            }
+        }
+    })
+    .await;
+    let index = match index {
+        Ok(Ok(index)) => index,
+        Ok(Err(_)) | Err(_) => {
+            running.shutdown().await;
+            return Err(format!("entity `{entity_id}` didn't show up").into());
+        }
+    };
+
+    // without Igloo, sub-device entities stay on the node's device 0
+    running
+        .control_tx
+        .send(DeviceControl::Write(0, index, comps))
+        .await?;
+
+    // print what the device answers with
+    let _ = timeout(CALL_ECHO, async {
+        while let Ok(msg) = running.igloo_rx.recv().await {
+            if let Some((i, comps)) = entities.handle(msg)
+                && i == index
+            {
+                println!("{:<40} {comps:?}", entities.id(i));
+            }
+        }
+    })
+    .await;
+
+    running.shutdown().await;
+    Ok(())
+}
+
+async fn ping(target: Target, count: u32) -> Result<(), Box<dyn Error>> {
+    let mut device = device(&target)?;
+    device.connect().await?;
+
+    for i in 0..count {
+        if i > 0 {
+            sleep(Duration::from_secs(1)).await;
+        }
+
+        let sent = Instant::now();
+        device
+            .send_msg(MessageType::PingRequest, &api::PingRequest {})
+            .await?;
+        let res = timeout(PING_TIMEOUT, async {
+            loop {
+                let (msg_type, _) = device.next_msg().await?;
+                if msg_type == MessageType::PingResponse {
+                    return Ok::<_, DeviceError>(());
+                }
+            }
+        })
+        .await;
+
+        match res {
+            Ok(Ok(())) => println!("pong from {}: {:.1?}", target.ip, sent.elapsed()),
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => println!("no pong from {} within {PING_TIMEOUT:?}", target.ip),
+        }
+    }
+
+    let _ = device.disconnect().await;
+    Ok(())
+}
+
+/// `on`/`off`, numbers and text as the matching component, anything
+/// else as JSON components
+fn parse_value(value: Option<&str>) -> Result<Vec<Component>, serde_json::Error> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+
+    Ok(match value {
+        "on" | "true" => vec![Component::Switch(true)],
+        "off" | "false" => vec![Component::Switch(false)],
+        json if json.starts_with('[') => serde_json::from_str(json)?,
+        json if json.starts_with('{') => vec![serde_json::from_str(json)?],
+        value => match value.parse() {
+            Ok(number) => vec![Component::Real(number)],
+            Err(_) => vec![Component::Text(value.to_string())],
+        },
+    })
+}
+
+/// A device running like it would under Igloo, with us standing in for Igloo
+struct Running {
+    igloo_rx: kanal::AsyncReceiver<ExtensionToIgloo>,
+    control_tx: kanal::AsyncSender<DeviceControl>,
+    task: tokio::task::JoinHandle<Result<(), DeviceError>>,
+}
+
+impl Running {
+    fn spawn(device: Device) -> Self {
+        let (igloo_tx, igloo_rx) = kanal::unbounded_async();
+        let (control_tx, control_rx) = kanal::bounded_async(8);
+        let task = tokio::spawn(device.run(igloo_tx, control_rx));
+        Self {
+            igloo_rx,
+            control_tx,
+            task,
+        }
+    }
+
+    async fn shutdown(self) {
+        let (reply, reply_rx) = oneshot::channel();
+        if self
+            .control_tx
+            .send(DeviceControl::Shutdown { reply })
+            .await
+            .is_ok()
+        {
+            let _ = reply_rx.await;
+        }
+        if let Ok(Err(e)) = self.task.await {
+            eprintln!("error: {e}");
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entity {
+    id: String,
+    name: String,
+    comps: Vec<Component>,
+}
+
+/// What Igloo would know about the device's entities
+#[derive(Default)]
+struct Entities {
+    by_index: BTreeMap<usize, Entity>,
+}
+
+impl Entities {
+    /// Track a message meant for Igloo. Returns state writes.
+    fn handle(&mut self, msg: ExtensionToIgloo) -> Option<(usize, Vec<Component>)> {
+        match msg {
+            ExtensionToIgloo::RegisterEntity {
+                entity_id,
+                entity_index,
+                ..
+            } => {
+                self.by_index.entry(entity_index).or_default().id = entity_id;
+                None
+            }
+            ExtensionToIgloo::WriteComponents { entity, comps, .. } => {
+                self.by_index.entry(entity).or_default().comps = comps.clone();
+                Some((entity, comps))
+            }
+            ExtensionToIgloo::Custom { name, payload } if name == custom::ENTITY_METADATA => {
+                if let Ok(metadata) = serde_json::from_value::<EntityMetadata>(payload) {
+                    self.by_index.entry(metadata.entity).or_default().name = metadata.name;
+                }
+                None
+            }
+            ExtensionToIgloo::Custom { name, payload } if name == custom::ERROR => {
+                // already logged, but that's filtered to warnings
+                if let Some(message) = payload.get("message").and_then(|m| m.as_str()) {
+                    eprintln!("error: {message}");
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn id(&self, index: usize) -> &str {
+        self.by_index
+            .get(&index)
+            .map(|entity| entity.id.as_str())
+            .unwrap_or("?")
+    }
+
+    fn index_of(&self, id: &str) -> Option<usize> {
+        self.by_index
+            .iter()
+            .find(|(_, entity)| entity.id == id)
+            .map(|(index, _)| *index)
+    }
+}
@@ -0,0 +1,87 @@
+//! Feed a capture written by a device's `capture` option through a
+//! [`Device`], printing what it sends to Igloo, to reproduce bugs offline.
+//! `esphome-replay` prints the capture itself.
+
+use bytes::BytesMut;
+use igloo_esphome::{
+    connection::capture::{self, Direction},
+    device::{ConnectionParams, Device},
+    logging,
+};
+use std::process::exit;
+
+const USAGE: &str = "usage: igloo-replay <capture>";
+
+#[tokio::main]
+async fn main() {
+    let _ = logging::init();
+
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                exit(2);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("reading {path}: {e}");
+            exit(1);
+        }
+    };
+
+    // stands in for Igloo, drained after every message
+    let (igloo_tx, igloo_rx) = kanal::unbounded_async();
+    let mut device = Device::new(0, ConnectionParams::default());
+
+    let mut start = None;
+    for (line, record) in capture::read_capture(&content).enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("line {}: {e}", line + 1);
+                continue;
+            }
+        };
+        // what we sent is only in the capture for context
+        if record.dir != Direction::Rx {
+            continue;
+        }
+        let bytes = match record.bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("line {}: invalid data: {e}", line + 1);
+                continue;
+            }
+        };
+
+        let start = *start.get_or_insert(record.time_us);
+        let elapsed = record.time_us.saturating_sub(start) as f64 / 1_000_000.;
+        let Some(msg_type) = record.msg_type() else {
+            println!("{elapsed:>10.3}s <- unknown message {}", record.msg_id);
+            continue;
+        };
+        println!("{elapsed:>10.3}s <- {msg_type}");
+
+        let msg = BytesMut::from(&bytes[..]);
+        if let Err(e) = device.replay_msg(&igloo_tx, msg_type, msg).await {
+            println!("    device error: {e}");
+        }
+        while let Ok(Some(msg)) = igloo_rx.try_recv() {
+            println!("    => igloo {msg:?}");
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    config::ConfigManager,
+    custom::{
+        self, CommandResult, DeviceListEntry, DeviceListStatus, ErrorKind, ErrorReport,
+        RemovedDevice, UpdateDevice,
+    },
+    device::{ConnectionParams, Device, DeviceControl},
+    logging::LogFilter,
+    secret::Secret,
+};
+use futures_util::future::join_all;
+use igloo_interface::ipc::{AsyncWriteExtensionToIgloo, ExtensionToIgloo};
+use rustc_hash::FxHashMap;
+use serde::de::DeserializeOwned;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{Mutex, oneshot},
+    time::timeout,
+};
+use tracing::{Instrument, error, warn};
+
+/// How long a device gets to answer [`custom::LIST_DEVICES`]
+pub const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Send a [`CommandResult`] to Igloo, and an [`custom::ERROR`] if it failed
+pub async fn reply(igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>, result: CommandResult) {
+    if let Some(e) = &result.error {
+        custom::report(igloo_tx, e.clone()).await;
+    }
+
+    if let Err(e) = custom::send(igloo_tx, custom::COMMAND_RESULT, &result).await {
+        error!(
+            command = result.command,
+            "error sending result to Igloo: {e}"
+        );
+    }
+}
+
+/// Parse a command's payload, replying to Igloo if it doesn't fit
+pub async fn parse<T: DeserializeOwned>(
+    igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+    command: &str,
+    payload: serde_json::Value,
+) -> Option<T> {
+    match serde_json::from_value(payload) {
+        Ok(cmd) => Some(cmd),
+        Err(e) => {
+            let e = ErrorReport::new(ErrorKind::InvalidParams, e);
+            reply(igloo_tx, CommandResult::new(command, None, Err(e))).await;
+            None
+        }
+    }
+}
+
+pub async fn unknown_device(
+    igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+    command: &str,
+    did: u64,
+) {
+    let e = ErrorReport::new(ErrorKind::InvalidParams, "unknown device");
+    let res = CommandResult::new(command, Some(did), Err(e));
+    reply(igloo_tx, res).await;
+}
+
+/// Hand a device a [`DeviceControl`] and wait for its answer
+async fn ask<T>(
+    device_tx: &kanal::AsyncSender<DeviceControl>,
+    control: impl FnOnce(oneshot::Sender<T>) -> DeviceControl,
+) -> Result<T, ErrorReport> {
+    let (reply, reply_rx) = oneshot::channel();
+    device_tx
+        .send(control(reply))
+        .await
+        .map_err(|_| ErrorReport::new(ErrorKind::Internal, "device isn't running"))?;
+    reply_rx
+        .await
+        .map_err(|_| ErrorReport::new(ErrorKind::Internal, "device stopped before answering"))
+}
+
+/// Saving the config failed
+fn config_error(e: impl std::fmt::Display) -> ErrorReport {
+    ErrorReport::new(ErrorKind::Internal, format!("saving config: {e}"))
+}
+
+/// Connect to a new device and ask Igloo to create it. Answered once
+/// Igloo sends `DeviceCreated`, or right away if it fails.
+pub async fn add_device(
+    pending_creation: Arc<Mutex<FxHashMap<String, Device>>>,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+    params: ConnectionParams,
+) {
+    let mut device = Device::new(0, params);
+    let span = device.span();
+    let result = async {
+        let info = device.connect().await?;
+        let name = custom::display_name(&info).to_string();
+        device.params.name = Some(name.clone());
+
+        // before asking, so DeviceCreated always finds it. Igloo answers by
+        // name alone, so another device being added under it gets a numbered one.
+        let mut pending = pending_creation.lock().await;
+        let mut key = name.clone();
+        for n in 2.. {
+            if !pending.contains_key(&key) {
+                break;
+            }
+            key = format!("{name} ({n})");
+        }
+        pending.insert(key.clone(), device);
+        drop(pending);
+        if let Err(e) = igloo_tx.create_device(key.clone()).await {
+            pending_creation.lock().await.remove(&key);
+            return Err(ErrorReport::new(ErrorKind::Internal, e));
+        }
+        Ok(())
+    }
+    .instrument(span)
+    .await;
+
+    if result.is_err() {
+        reply(
+            &igloo_tx,
+            CommandResult::new(custom::ADD_DEVICE, None, result),
+        )
+        .await;
+    }
+}
+
+/// Hand a new noise PSK to a running device, persisting it once the device
+/// confirms a connection with it works
+pub async fn change_noise_psk(
+    cm: Arc<Mutex<ConfigManager>>,
+    device_tx: kanal::AsyncSender<DeviceControl>,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+    command: String,
+    did: u64,
+    psk: Option<Secret>,
+) {
+    let result = async {
+        let params = ask(&device_tx, |reply| DeviceControl::SetNoisePsk {
+            psk,
+            reply,
+        })
+        .await??;
+
+        let mut cm = cm.lock().await;
+        cm.set_device(did, params).await.map_err(config_error)
+    }
+    .await;
+
+    reply(&igloo_tx, CommandResult::new(&command, Some(did), result)).await;
+}
+
+/// Stop a device and forget it, answering with its sub-devices' Igloo devices
+pub async fn remove_device(
+    cm: Arc<Mutex<ConfigManager>>,
+    log_filter: Arc<LogFilter>,
+    device_tx: kanal::AsyncSender<DeviceControl>,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+    did: u64,
+) {
+    // already stopped is fine, it just needs to be gone
+    if let Err(e) = ask(&device_tx, |reply| DeviceControl::Shutdown { reply }).await {
+        warn!(device = did, "didn't shut down cleanly: {}", e.message);
+    }
+    drop(device_tx);
+
+    // there's no IPC message to remove a device, Igloo gets their IDs instead
+    let mut cm = cm.lock().await;
+    let sub_devices: Vec<_> = cm
+        .devices()
+        .get(&did)
+        .map(|params| params.sub_devices.values().copied().collect())
+        .unwrap_or_default();
+    let result = cm.remove_device(did).await.map_err(config_error);
+    if let Err(e) = log_filter.apply_config(&cm) {
+        warn!(device = did, "keeping the previous log levels: {e}");
+    }
+    drop(cm);
+
+    let res = CommandResult::new(custom::REMOVE_DEVICE, Some(did), result)
+        .with_data(&RemovedDevice { sub_devices });
+    reply(&igloo_tx, res).await;
+}
+
+/// Reconnect a device with changed params, persisting them once they work
+pub async fn update_device(
+    cm: Arc<Mutex<ConfigManager>>,
+    log_filter: Arc<LogFilter>,
+    device_tx: kanal::AsyncSender<DeviceControl>,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+    cmd: UpdateDevice,
+) {
+    let did = cmd.device;
+    let result = async {
+        let Some(mut params) = cm.lock().await.devices().get(&did).cloned() else {
+            return Err(ErrorReport::new(
+                ErrorKind::InvalidParams,
+                "device isn't in the config",
+            ));
+        };
+
+        if let Some(ip) = cmd.ip {
+            params.ip = ip;
+        }
+        if let Some(psk) = cmd.noise_psk {
+            params.noise_psk = Some(psk);
+        }
+        if let Some(password) = cmd.password {
+            params.password = Some(password);
+        }
+        if let Some(name) = cmd.name {
+            params.name = Some(name);
+        }
+
+        let params = ask(&device_tx, |reply| DeviceControl::Update {
+            params: Box::new(params),
+            reply,
+        })
+        .await??;
+
+        let mut cm = cm.lock().await;
+        cm.set_device(did, params).await.map_err(config_error)?;
+        if let Err(e) = log_filter.apply_config(&cm) {
+            warn!(device = did, "keeping the previous log levels: {e}");
+        }
+        Ok(())
+    }
+    .await;
+
+    let res = CommandResult::new(custom::UPDATE_DEVICE, Some(did), result);
+    reply(&igloo_tx, res).await;
+}
+
+pub async fn reconnect_device(
+    device_tx: kanal::AsyncSender<DeviceControl>,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+    did: u64,
+) {
+    let result = ask(&device_tx, |reply| DeviceControl::Reconnect { reply })
+        .await
+        .and_then(|res| res.map_err(ErrorReport::from));
+
+    let res = CommandResult::new(custom::RECONNECT_DEVICE, Some(did), result);
+    reply(&igloo_tx, res).await;
+}
+
+/// Every configured device with its live status
+pub async fn list_devices(
+    cm: Arc<Mutex<ConfigManager>>,
+    device_txs: Vec<(u64, kanal::AsyncSender<DeviceControl>)>,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+) {
+    let devices = cm.lock().await.devices().clone();
+
+    // all at once, so offline devices don't add up their timeouts
+    let mut entries = join_all(devices.into_iter().map(|(did, params)| {
+        let device_tx = device_txs.iter().find(|(id, _)| *id == did);
+        async move {
+            let mut entry = DeviceListEntry {
+                device: did,
+                name: params.name,
+                ip: params.ip,
+                encrypted: params.noise_psk.is_some(),
+                status: None,
+            };
+
+            // a device busy connecting may not answer
+            if let Some((_, device_tx)) = device_tx
+                && let Ok(Ok(status)) = timeout(
+                    STATUS_TIMEOUT,
+                    ask(device_tx, |reply| DeviceControl::Status { reply }),
+                )
+                .await
+            {
+                entry.status = Some(DeviceListStatus::from(&status));
+            }
+
+            entry
+        }
+    }))
+    .await;
+    entries.sort_by_key(|entry| entry.device);
+
+    let res = CommandResult::new(custom::LIST_DEVICES, None, Ok(())).with_data(&entries);
+    reply(&igloo_tx, res).await;
+}
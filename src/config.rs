@@ -0,0 +1,264 @@
+use crate::{
+    device::ConnectionParams,
+    secret::{DeviceSecrets, FileSecretStore, SECRETS_FILE, Secret, SecretStore},
+};
+use igloo_interface::ipc;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+use tracing::warn;
+
+pub const CONFIG_FILE: &str = "config.toml";
+/// the config as it was before the last save, never one that failed to load
+pub const BACKUP_FILE: &str = "config.toml.bak";
+
+/// Bump whenever the format changes and add a migration to [`MIGRATIONS`]
+pub const CONFIG_VERSION: u32 = 1;
+
+type Migration = fn(&mut toml::Table) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a config from version `n` to `n + 1`
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_v0_to_v1];
+
+#[derive(Debug)]
+pub struct ConfigManager {
+    path: PathBuf,
+    config: Config,
+    /// holds every secret not read from the environment
+    secrets: Box<dyn SecretStore>,
+    /// last config loaded or saved, serialized. Becomes the backup on the next save.
+    last_good: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// missing = before versioning (0)
+    #[serde(default)]
+    version: u32,
+    /// `trace`, `debug`, `info`, `warn`, `error` or `off`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    log_level: Option<String>,
+    /// maps Persisnt Igloo Device ID -> Connection Params
+    #[serde(default, rename = "device")]
+    devices: FxHashMap<u64, ConnectionParams>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            log_level: None,
+            devices: FxHashMap::default(),
+        }
+    }
+}
+
+impl ConfigManager {
+    pub async fn load() -> Result<Self, Box<dyn Error>> {
+        let data_path = ipc::get_data_path();
+        let path: PathBuf = [&data_path, CONFIG_FILE].iter().collect();
+        let backup_path: PathBuf = [&data_path, BACKUP_FILE].iter().collect();
+
+        let (mut config, mut dirty) = match Self::read(&path).await {
+            Ok(res) => res,
+            Err(e) if fs::try_exists(&backup_path).await? => {
+                warn!("{e}. Falling back to {}", backup_path.to_string_lossy());
+                // write it back so config.toml is valid again
+                let (config, _) = Self::read(&backup_path).await?;
+                (config, true)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let secrets_path: PathBuf = [&data_path, SECRETS_FILE].iter().collect();
+        let mut secrets: Box<dyn SecretStore> = Box::new(FileSecretStore::new(secrets_path));
+        let mut stored = secrets.load().await?;
+
+        // secrets written straight into config.toml get moved to the store
+        for (did, params) in config.devices.iter_mut() {
+            dirty |= params.noise_psk.as_ref().is_some_and(Secret::is_stored)
+                || params.password.as_ref().is_some_and(Secret::is_stored);
+
+            if let Some(device_secrets) = stored.remove(did) {
+                params.noise_psk = params.noise_psk.take().or(device_secrets.noise_psk);
+                params.password = params.password.take().or(device_secrets.password);
+            }
+        }
+
+        let mut cm = Self {
+            path,
+            config,
+            secrets,
+            last_good: String::new(),
+        };
+        // never the config.toml that failed to load
+        cm.last_good = toml::to_string_pretty(&cm.split_secrets().0)?;
+        if dirty {
+            cm.save().await?;
+        }
+        Ok(cm)
+    }
+
+    /// Read and migrate a config. Also returns whether it was migrated.
+    async fn read(path: &Path) -> Result<(Config, bool), Box<dyn Error>> {
+        if !fs::try_exists(path).await? {
+            return Ok((Config::default(), false));
+        }
+
+        let meta = fs::metadata(path).await?;
+        if meta.is_dir() {
+            return Err(format!("{} should not be directory", path.to_string_lossy()).into());
+        }
+
+        let content = fs::read_to_string(path).await?;
+
+        // only the message, the snippet could contain a secret
+        let mut table: toml::Table = toml::from_str(&content)
+            .map_err(|e| format!("{} is invalid: {}", path.to_string_lossy(), e.message()))?;
+
+        let version = match table.get("version") {
+            None => 0,
+            Some(toml::Value::Integer(v)) => u32::try_from(*v).unwrap_or(u32::MAX),
+            Some(_) => {
+                return Err(format!("{} has an invalid version", path.to_string_lossy()).into());
+            }
+        };
+
+        if version > CONFIG_VERSION {
+            return Err(format!(
+                "{} is version {version}, but only up to {CONFIG_VERSION} is supported",
+                path.to_string_lossy()
+            )
+            .into());
+        }
+
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(&mut table)
+                .map_err(|e| format!("migrating {}: {e}", path.to_string_lossy()))?;
+        }
+        table.insert(
+            "version".into(),
+            toml::Value::Integer(CONFIG_VERSION.into()),
+        );
+
+        let config = Config::deserialize(table)
+            .map_err(|e| format!("{} is invalid: {}", path.to_string_lossy(), e.message()))?;
+
+        Ok((config, version != CONFIG_VERSION))
+    }
+
+    pub async fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        let (config, secrets) = self.split_secrets();
+
+        // secrets first, so a crash in between never loses one
+        self.secrets.save(&secrets).await?;
+
+        let content = toml::to_string_pretty(&config)?;
+
+        let backup_path = self.path.with_file_name(BACKUP_FILE);
+        write_atomic(&backup_path, self.last_good.as_bytes(), 0o644).await?;
+        write_atomic(&self.path, content.as_bytes(), 0o644).await?;
+        self.last_good = content;
+
+        Ok(())
+    }
+
+    /// The config as written to `config.toml`, and the secrets kept out of it
+    fn split_secrets(&self) -> (Config, FxHashMap<u64, DeviceSecrets>) {
+        let mut config = self.config.clone();
+        let mut secrets = FxHashMap::default();
+        for (did, params) in config.devices.iter_mut() {
+            let device_secrets = DeviceSecrets {
+                noise_psk: params.noise_psk.take_if(|psk| psk.is_stored()),
+                password: params.password.take_if(|password| password.is_stored()),
+            };
+            if !device_secrets.is_empty() {
+                secrets.insert(*did, device_secrets);
+            }
+        }
+        (config, secrets)
+    }
+
+    pub fn devices(&self) -> &FxHashMap<u64, ConnectionParams> {
+        &self.config.devices
+    }
+
+    pub fn log_level(&self) -> Option<&str> {
+        self.config.log_level.as_deref()
+    }
+
+    /// Add or replace a device and save
+    pub async fn set_device(
+        &mut self,
+        did: u64,
+        params: ConnectionParams,
+    ) -> Result<(), Box<dyn Error>> {
+        self.config.devices.insert(did, params);
+        self.save().await
+    }
+
+    /// Record the Igloo device made for one of a node's sub-devices and save
+    pub async fn set_sub_device(
+        &mut self,
+        did: u64,
+        device_id: u32,
+        sub_did: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(params) = self.config.devices.get_mut(&did) else {
+            return Ok(());
+        };
+        params.sub_devices.insert(device_id, sub_did);
+        self.save().await
+    }
+
+    /// Forget a device (and its secrets) and save
+    pub async fn remove_device(&mut self, did: u64) -> Result<(), Box<dyn Error>> {
+        self.config.devices.remove(&did);
+        self.save().await
+    }
+}
+
+/// Write to a temporary file, fsync it, then rename it over `path`.
+/// Readers (and crashes) only ever see the old or the new file.
+pub async fn write_atomic(path: &Path, content: &[u8], mode: u32) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    // leftover from a crash, `mode` only applies to new files
+    let _ = fs::remove_file(&tmp_path).await;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let mut file = options.open(&tmp_path).await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    fs::rename(&tmp_path, path).await?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir).await?.sync_all().await?;
+    }
+
+    Ok(())
+}
+
+/// v0 had no `version` field, nothing else changed
+fn migrate_v0_to_v1(_table: &mut toml::Table) -> Result<(), String> {
+    Ok(())
+}
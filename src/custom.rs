@@ -0,0 +1,348 @@
+use igloo_interface::ipc::ExtensionToIgloo;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, time::UNIX_EPOCH};
+use tracing::error;
+
+use crate::{
+    api,
+    connection::error::ConnectionError,
+    device::{ConnectionParams, DeviceError},
+    entity::status::DeviceStatus,
+    secret::{self, Secret},
+};
+
+pub use crate::connection::error::ErrorKind;
+
+/// Sent whenever a device (re)connects so Igloo can name and place it
+pub const DEVICE_METADATA: &str = "device_metadata";
+
+/// Sent whenever an entity is (re)registered, since its name can change
+pub const ENTITY_METADATA: &str = "entity_metadata";
+
+/// Reply to any custom command from Igloo
+pub const COMMAND_RESULT: &str = "command_result";
+
+/// Sent for every failure, whether or not it answers a command
+pub const ERROR: &str = "error";
+
+/// Commands from Igloo
+pub const ADD_DEVICE: &str = "add_device";
+pub const SET_NOISE_PSK: &str = "set_noise_psk";
+/// generate a new random key
+pub const ROTATE_NOISE_PSK: &str = "rotate_noise_psk";
+/// switch to plaintext
+pub const REMOVE_NOISE_PSK: &str = "remove_noise_psk";
+pub const REMOVE_DEVICE: &str = "remove_device";
+pub const UPDATE_DEVICE: &str = "update_device";
+pub const LIST_DEVICES: &str = "list_devices";
+pub const RECONNECT_DEVICE: &str = "reconnect_device";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCommand {
+    pub device: u64,
+}
+
+/// What Igloo may set on a new device, secrets only as plain strings.
+/// The rest of [`ConnectionParams`] is for config.toml alone.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddDevice {
+    pub ip: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "secret::plain")]
+    pub noise_psk: Option<Secret>,
+    #[serde(default, deserialize_with = "secret::plain")]
+    pub password: Option<Secret>,
+    #[serde(default)]
+    pub enable_entities: Vec<String>,
+    #[serde(default)]
+    pub disable_entities: Vec<String>,
+}
+
+impl AddDevice {
+    pub fn into_params(self) -> ConnectionParams {
+        ConnectionParams {
+            ip: self.ip,
+            name: self.name,
+            noise_psk: self.noise_psk,
+            password: self.password,
+            enable_entities: self.enable_entities,
+            disable_entities: self.disable_entities,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NoisePskCommand {
+    pub device: u64,
+    /// only for [`SET_NOISE_PSK`]
+    #[serde(default, deserialize_with = "secret::plain")]
+    pub psk: Option<Secret>,
+}
+
+/// Fields left out stay as they are
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateDevice {
+    pub device: u64,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default, deserialize_with = "secret::plain")]
+    pub noise_psk: Option<Secret>,
+    #[serde(default, deserialize_with = "secret::plain")]
+    pub password: Option<Secret>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    pub message: String,
+}
+
+impl ErrorReport {
+    pub fn new(kind: ErrorKind, message: impl Display) -> Self {
+        Self {
+            kind,
+            device: None,
+            command: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn device(mut self, device: u64) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = Some(command.to_string());
+        self
+    }
+}
+
+impl From<DeviceError> for ErrorReport {
+    fn from(e: DeviceError) -> Self {
+        Self::new(e.kind(), e)
+    }
+}
+
+impl From<ConnectionError> for ErrorReport {
+    fn from(e: ConnectionError) -> Self {
+        Self::new(e.kind(), e)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandResult {
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<u64>,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+    /// command specific, ie. the devices for [`LIST_DEVICES`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl CommandResult {
+    pub fn new(command: &str, device: Option<u64>, result: Result<(), ErrorReport>) -> Self {
+        let error = result.err().map(|mut e| {
+            e.command = Some(command.to_string());
+            e.device = e.device.or(device);
+            e
+        });
+        Self {
+            command: command.to_string(),
+            device,
+            ok: error.is_none(),
+            error,
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: &impl Serialize) -> Self {
+        self.data = serde_json::to_value(data).ok();
+        self
+    }
+}
+
+/// [`REMOVE_DEVICE`]'s data
+#[derive(Debug, Clone, Serialize)]
+pub struct RemovedDevice {
+    /// Igloo devices made for the node's sub-devices, left for Igloo to remove
+    pub sub_devices: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceListEntry {
+    pub device: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub ip: String,
+    pub encrypted: bool,
+    /// `None` if the device didn't answer in time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<DeviceListStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceListStatus {
+    pub connected: bool,
+    /// unix seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    pub reconnects: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping_latency_ms: Option<f64>,
+}
+
+impl From<&DeviceStatus> for DeviceListStatus {
+    fn from(status: &DeviceStatus) -> Self {
+        Self {
+            connected: status.connected,
+            last_seen: status
+                .last_seen
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            reconnects: status.reconnects,
+            ping_latency_ms: status.ping_latency.map(|l| l.as_secs_f64() * 1000.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityMetadata {
+    pub device: u64,
+    pub entity: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceMetadata {
+    pub device: u64,
+    /// human readable name (friendly name, or node name if unset)
+    pub name: String,
+    pub node_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_area: Option<String>,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_version: Option<String>,
+    /// link to the device's built-in web server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// for sub-devices, the Igloo device of the node they're part of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<u64>,
+}
+
+impl DeviceMetadata {
+    pub fn new(device: u64, host: &str, info: &api::DeviceInfoResponse) -> Self {
+        Self {
+            device,
+            name: display_name(info).to_string(),
+            node_name: info.name.clone(),
+            suggested_area: non_empty(area(info)),
+            model: info.model.clone(),
+            project_name: non_empty(&info.project_name),
+            project_version: non_empty(&info.project_version),
+            url: match info.webserver_port {
+                0 => None,
+                80 => Some(format!("http://{host}/")),
+                port => Some(format!("http://{host}:{port}/")),
+            },
+            node: None,
+        }
+    }
+
+    /// Metadata for one of the sub-devices a node declares
+    pub fn sub_device(
+        device: u64,
+        node: u64,
+        host: &str,
+        info: &api::DeviceInfoResponse,
+        sub: &api::DeviceInfo,
+    ) -> Self {
+        Self {
+            name: sub.name.clone(),
+            suggested_area: area_name(info, sub.area_id).and_then(non_empty),
+            node: Some(node),
+            ..Self::new(device, host, info)
+        }
+    }
+}
+
+/// Friendly name, falling back to the node name
+pub fn display_name(info: &api::DeviceInfoResponse) -> &str {
+    if info.friendly_name.is_empty() {
+        &info.name
+    } else {
+        &info.friendly_name
+    }
+}
+
+/// The node's area, falling back to the suggested area from older firmware
+pub fn area(info: &api::DeviceInfoResponse) -> &str {
+    match &info.area {
+        Some(area) if !area.name.is_empty() => &area.name,
+        _ => &info.suggested_area,
+    }
+}
+
+/// Name of one of the areas the node declares
+pub fn area_name(info: &api::DeviceInfoResponse, area_id: u32) -> Option<&str> {
+    info.areas
+        .iter()
+        .find(|area| area.area_id == area_id)
+        .map(|area| area.name.as_str())
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+/// Send a custom message to Igloo
+pub async fn send(
+    igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+    name: &str,
+    payload: &impl Serialize,
+) -> Result<(), kanal::SendError> {
+    let payload = match serde_json::to_value(payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!(name, "failed to serialize custom message, skipping: {e}");
+            return Ok(());
+        }
+    };
+
+    igloo_tx
+        .send(ExtensionToIgloo::Custom {
+            name: name.to_string(),
+            payload,
+        })
+        .await
+}
+
+/// Report a failure to Igloo, logging it too
+pub async fn report(igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>, report: ErrorReport) {
+    error!(
+        kind = ?report.kind,
+        device = report.device,
+        command = report.command,
+        "{}",
+        report.message
+    );
+
+    if let Err(e) = send(igloo_tx, ERROR, &report).await {
+        error!("error sending error report to Igloo: {e}");
+    }
+}
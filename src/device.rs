@@ -3,69 +3,202 @@ use igloo_interface::{
     Component,
     ipc::{AsyncWriteExtensionToIgloo, ExtensionToIgloo},
 };
-use prost::Message;
+use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    time::{SystemTime, UNIX_EPOCH},
+    collections::{BTreeMap, HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 use thiserror::Error;
+use tokio::{
+    sync::{Mutex, oneshot},
+    time::{MissedTickBehavior, interval, sleep},
+};
+use tracing::{Span, debug, info, info_span, trace, warn};
 
 use crate::{
     api,
+    client::{ApiVersion, Client, ClientError, EntityKey},
     connection::{
-        base::{Connection, Connectionable},
+        base::Connection,
+        capture::Recorder,
         error::ConnectionError,
-        noise::NoiseConnection,
+        noise::{self, NoiseConnection},
         plain::PlainConnection,
+        timeouts::Timeouts,
+    },
+    custom::{self, DeviceMetadata, EntityMetadata, ErrorKind, ErrorReport},
+    entity::{
+        self, EntityRegister, EntityUpdate, SyntheticEntity,
+        identity::{self, EntityIds},
+        status::DeviceStatus,
     },
-    entity::{self, EntityRegister, EntityUpdate},
-    model::{EntityType, MessageType},
+    model::{AnyMessage, EntityType, MessageType, decode_message},
+    secret::Secret,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// How often a ping is sent to the device
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(20);
+/// How long a ping can go unanswered before the connection is considered dead
+pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+pub const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// How long Igloo gets to create the device for a sub-device
+pub const SUB_DEVICE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages from the device we have no use for (yet)
+const IGNORED_MESSAGES: &[MessageType] = &[
+    MessageType::GetTimeResponse,
+    MessageType::HomeassistantServiceResponse,
+    MessageType::SubscribeHomeAssistantStateResponse,
+    MessageType::VoiceAssistantRequest,
+    MessageType::VoiceAssistantAudio,
+    MessageType::VoiceAssistantAnnounceFinished,
+    // no Bluetooth proxy support yet
+    MessageType::BluetoothLEAdvertisementResponse,
+    MessageType::BluetoothDeviceConnectionResponse,
+    MessageType::BluetoothGATTGetServicesResponse,
+    MessageType::BluetoothGATTGetServicesDoneResponse,
+    MessageType::BluetoothGATTReadResponse,
+    MessageType::BluetoothGATTNotifyDataResponse,
+    MessageType::BluetoothConnectionsFreeResponse,
+    MessageType::BluetoothGATTErrorResponse,
+    MessageType::BluetoothGATTWriteResponse,
+    MessageType::BluetoothGATTNotifyResponse,
+    MessageType::BluetoothDevicePairingResponse,
+    MessageType::BluetoothDeviceUnpairingResponse,
+    MessageType::BluetoothDeviceClearCacheResponse,
+    MessageType::BluetoothLERawAdvertisementsResponse,
+    MessageType::BluetoothScannerStateResponse,
+];
+
+/// Whether we deliberately do nothing with a message from the device
+fn is_ignored(msg_type: &MessageType) -> bool {
+    IGNORED_MESSAGES.contains(msg_type)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ConnectionParams {
     pub ip: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub noise_psk: Option<String>,
+    pub noise_psk: Option<Secret>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub password: Option<String>,
+    pub password: Option<Secret>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// entity IDs to register even if disabled by default
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enable_entities: Vec<String>,
+    /// entity IDs to never register
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disable_entities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Timeouts::is_default")]
+    pub timeouts: Timeouts,
+    /// overrides the global log level for just this device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// write every decrypted message to this file, see `esphome-replay`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture: Option<PathBuf>,
+    /// maps ESPHome sub-device ID -> the Igloo device made for it
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sub_devices: BTreeMap<u32, u64>,
+}
+
+/// A sub-device waiting on Igloo's `DeviceCreated`
+pub struct PendingSubDevice {
+    /// Igloo device of the node it's part of
+    pub node: u64,
+    /// ESPHome sub-device ID
+    pub device_id: u32,
+    pub reply: oneshot::Sender<u64>,
+}
+
+/// Sub-devices waiting on Igloo's `DeviceCreated`, by the name it was asked for
+pub type PendingSubDevices = Arc<Mutex<FxHashMap<String, PendingSubDevice>>>;
+
+/// Sent from main to a running [`Device`]
+pub enum DeviceControl {
+    /// Igloo wrote components to an entity of the node or one of its sub-devices
+    Write(u64, usize, Vec<Component>),
+    /// Switch to a new noise PSK, or plaintext with `None`.
+    /// Replies with the params to persist once the new key works.
+    SetNoisePsk {
+        psk: Option<Secret>,
+        reply: oneshot::Sender<Result<ConnectionParams, DeviceError>>,
+    },
+    /// Reconnect with new params, keeping the old ones if that fails.
+    /// Replies with the params to persist.
+    Update {
+        params: Box<ConnectionParams>,
+        reply: oneshot::Sender<Result<ConnectionParams, DeviceError>>,
+    },
+    /// Drop the connection and connect again right away
+    Reconnect {
+        reply: oneshot::Sender<Result<(), DeviceError>>,
+    },
+    Status {
+        reply: oneshot::Sender<DeviceStatus>,
+    },
+    /// Igloo created a sub-device's device after [`SUB_DEVICE_TIMEOUT`],
+    /// its entities move there next connection
+    SubDeviceCreated { device_id: u32, device: u64 },
+    /// Disconnect and stop running
+    Shutdown { reply: oneshot::Sender<()> },
 }
 
 pub struct Device {
     pub id: u64,
     pub params: ConnectionParams,
-    pub connection: Connection,
-    password: String,
-    connected: bool,
-    last_ping: Option<SystemTime>,
-    /// maps ESPHome entity key -> Igloo entity index
-    entity_key_to_index: HashMap<u32, usize>,
-    /// maps Igloo entity index -> ESPHome type,key
-    entity_index_to_info: Vec<(EntityType, u32)>,
-    next_entity_index: usize,
+    /// typed commands (`device.client.light(key).turn_on().send()`) and
+    /// the last known state of each entity
+    pub client: Client,
+    /// where sub-devices wait for Igloo to create their device.
+    /// Without it their entities go on the node's device.
+    pub pending_sub_devices: Option<PendingSubDevices>,
+    /// set by [`DeviceControl::Shutdown`]
+    stopping: bool,
+    status: DeviceStatus,
+    /// when the outstanding keepalive ping was sent
+    ping_sent: Option<Instant>,
+    /// set by [`Device::connect`]
+    pub info: Option<api::DeviceInfoResponse>,
+    /// last configuration reported by a voice assistant satellite
+    #[cfg(feature = "voice")]
+    pub(crate) voice_config: Option<api::VoiceAssistantConfigurationResponse>,
+    /// maps ESPHome entity -> Igloo device and entity index
+    entity_key_to_index: HashMap<EntityKey, (u64, usize)>,
+    /// ESPHome entities disabled by default or by config
+    disabled_keys: HashSet<EntityKey>,
+    /// maps synthetic entity -> Igloo entity index on the node's device
+    synthetic_to_index: HashMap<SyntheticEntity, usize>,
+    /// maps Igloo device -> Igloo entity index -> entity
+    entity_index_to_info: HashMap<u64, Vec<EntityRef>>,
+    /// entity IDs per Igloo device
+    entity_ids: HashMap<u64, EntityIds>,
+}
+
+/// What an Igloo entity index points to
+#[derive(Clone, Debug)]
+pub enum EntityRef {
+    /// ESPHome type,key
+    Esphome(EntityType, EntityKey),
+    Synthetic(SyntheticEntity),
 }
 
 #[derive(Error, Debug)]
 pub enum DeviceError {
     #[error("io error `{0}`")]
     IO(#[from] std::io::Error),
-    #[error("not connected")]
-    NotConnected,
-    #[error("device requested shutdown")]
-    DeviceRequestShutdown,
-    #[error("invalid password")]
-    InvalidPassword,
+    #[error(transparent)]
+    Client(#[from] ClientError),
     #[error("connection error `{0}`")]
     ConnectionError(#[from] ConnectionError),
     #[error("frame had wrong preamble `{0}`")]
     FrameHadWrongPreamble(u8),
-    #[error("system time error `{0}`")]
-    SystemTimeError(#[from] std::time::SystemTimeError),
-    #[error("system time error `{0}`")]
-    SystemTimeIntCastError(#[from] std::num::TryFromIntError),
     #[error("prost decode error `{0}`")]
     ProstDecodeError(#[from] prost::DecodeError),
     #[error("prost encode error `{0}`")]
@@ -76,239 +209,680 @@ pub enum DeviceError {
     UnknownEntityCategory(i32),
     #[error("wrong message type `{0}`")]
     WrongMessageType(MessageType),
+    #[error("entity listing timed out after {0:?}")]
+    ListEntitiesTimeout(Duration),
     #[error("unknown incoming message type `{0}`")]
     UnknownIncomingMessageType(MessageType),
     #[error("unknown log level `{0}`")]
     UnknownLogLevel(i32),
     #[error("entity doesn't exist: `{0}`")]
-    InvalidEntity(u16),
+    InvalidEntity(usize),
+    #[error("{0} doesn't support commands")]
+    UnsupportedCommand(String),
     #[error("sending to Igloo write task: `{0}`")]
     IglooSendError(#[from] kanal::SendError),
+    #[error("device didn't answer ping within {0:?}")]
+    PingTimeout(Duration),
+    #[error("device rejected the new noise PSK")]
+    NoisePskRejected,
+}
+
+impl DeviceError {
+    pub fn kind(&self) -> ErrorKind {
+        use DeviceError::*;
+        match self {
+            Client(e) => e.kind(),
+            ConnectionError(e) => e.kind(),
+            NoisePskRejected => ErrorKind::WrongPsk,
+            FrameHadWrongPreamble(_)
+            | ProstDecodeError(_)
+            | WrongMessageType(_)
+            | UnknownListEntitiesResponse(_)
+            | UnknownIncomingMessageType(_)
+            | UnknownEntityCategory(_)
+            | UnknownLogLevel(_) => ErrorKind::ProtocolMismatch,
+            ListEntitiesTimeout(_) | PingTimeout(_) => ErrorKind::Timeout,
+            IO(_) => ErrorKind::Disconnected,
+            InvalidEntity(_) | UnsupportedCommand(_) => ErrorKind::InvalidParams,
+            ProstEncodeError(_) | IglooSendError(_) => ErrorKind::Internal,
+        }
+    }
+
+    /// The connection is gone, so the session can't go on
+    fn is_connection_lost(&self) -> bool {
+        matches!(
+            self,
+            DeviceError::ConnectionError(_)
+                | DeviceError::Client(ClientError::ConnectionError(_) | ClientError::NotConnected)
+        )
+    }
+
+    /// The device asked us to disconnect, which ends the session cleanly
+    fn is_shutdown_request(&self) -> bool {
+        matches!(
+            self,
+            DeviceError::Client(ClientError::DeviceRequestShutdown)
+        )
+    }
 }
 
 impl Device {
     pub fn new(id: u64, params: ConnectionParams) -> Self {
-        let connection = match &params.noise_psk {
-            Some(noise_psk) => {
-                NoiseConnection::new(params.ip.clone(), noise_psk.to_string()).into()
-            }
-            None => PlainConnection::new(params.ip.clone()).into(),
-        };
-
         Device {
             id,
-            connection,
-            password: params.password.clone().unwrap_or_default(),
+            client: Self::new_client(&params),
+            pending_sub_devices: None,
             params,
-            connected: false,
-            last_ping: None,
+            stopping: false,
+            status: DeviceStatus::default(),
+            ping_sent: None,
+            info: None,
+            #[cfg(feature = "voice")]
+            voice_config: None,
             entity_key_to_index: HashMap::new(),
-            entity_index_to_info: Vec::new(),
-            next_entity_index: 0,
+            disabled_keys: HashSet::new(),
+            synthetic_to_index: HashMap::new(),
+            entity_index_to_info: HashMap::new(),
+            entity_ids: HashMap::new(),
+        }
+    }
+
+    fn new_client(params: &ConnectionParams) -> Client {
+        let mut connection: Connection = match &params.noise_psk {
+            Some(noise_psk) => NoiseConnection::new(
+                params.ip.clone(),
+                noise_psk.expose().to_string(),
+                params.timeouts.clone(),
+            )
+            .into(),
+            None => PlainConnection::new(params.ip.clone(), params.timeouts.clone()).into(),
+        };
+        if let Some(path) = &params.capture {
+            connection.set_recorder(Some(Arc::new(Recorder::new(path.clone()))));
         }
+        let password = params
+            .password
+            .as_ref()
+            .map(|password| password.expose().to_string())
+            .unwrap_or_default();
+        Client::new(connection)
+            .password(password)
+            .hello_timeout(params.timeouts.hello)
     }
 
+    /// Run the device until Igloo drops it, reconnecting whenever
+    /// the connection is lost
     pub async fn run(
         mut self,
         igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
-        in_rx: kanal::AsyncReceiver<(usize, Vec<Component>)>,
+        in_rx: kanal::AsyncReceiver<DeviceControl>,
     ) -> Result<(), DeviceError> {
-        if !self.connected {
-            unreachable!()
+        loop {
+            if !self.client.is_connected() {
+                self.reconnect(&igloo_tx, &in_rx).await?;
+            }
+            // reconnect gave up, shutting down or nothing left to control us
+            if self.stopping || !self.client.is_connected() {
+                return Ok(());
+            }
+
+            let res = self.run_session(&igloo_tx, &in_rx).await;
+
+            // session ended on purpose with a fresh connection (ie. new noise PSK)
+            if res.is_ok() && self.client.is_connected() && !self.stopping {
+                continue;
+            }
+
+            if self.client.is_connected() {
+                let _ = self.force_disconnect().await;
+            }
+            self.status.connected = false;
+            self.status.ping_latency = None;
+            self.publish_status(&igloo_tx).await?;
+
+            match res {
+                Ok(()) => info!("disconnected"),
+                Err(e) => self.report(&igloo_tx, e).await,
+            }
+
+            if self.stopping || in_rx.is_disconnected() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Connect with exponential backoff, dropping Igloo writes while disconnected.
+    /// Only gives up on [`DeviceControl::Shutdown`] or once `in_rx` closes.
+    async fn reconnect(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        in_rx: &kanal::AsyncReceiver<DeviceControl>,
+    ) -> Result<(), DeviceError> {
+        let mut delay = RECONNECT_MIN_DELAY;
+        // DeviceControl::Reconnect waiting on the next attempt
+        let mut reconnect_reply: Option<oneshot::Sender<_>> = None;
+        // only report when the reason changes, not every retry
+        let mut last_kind = None;
+        loop {
+            let res = self.connect().await.map(|_| ());
+            match &res {
+                Ok(()) => {
+                    if self.status.last_seen.is_some() {
+                        self.status.reconnects += 1;
+                    }
+                }
+                Err(e) => {
+                    warn!(kind = ?e.kind(), "error connecting: {e}. Retrying in {delay:?}");
+                    if last_kind.replace(e.kind()) != Some(e.kind()) {
+                        let report = ErrorReport::new(e.kind(), e).device(self.id);
+                        custom::report(igloo_tx, report).await;
+                    }
+                    let _ = self.client.force_disconnect().await;
+                    self.publish_status(igloo_tx).await?;
+                }
+            }
+            let connected = res.is_ok();
+            if let Some(reply) = reconnect_reply.take() {
+                let _ = reply.send(res);
+            }
+            if connected {
+                return Ok(());
+            }
+
+            let wait = sleep(delay);
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    res = in_rx.recv() => match res {
+                        Ok(DeviceControl::Write(did, eidx, _)) => {
+                            warn!(device = did, entity = eidx, "disconnected, dropping write");
+                        }
+                        Ok(DeviceControl::SetNoisePsk { psk, reply }) => {
+                            // may well be why we can't connect
+                            let res = self.set_noise_psk(psk).await;
+                            let connected = res.is_ok() && self.client.is_connected();
+                            let _ = reply.send(res.map(|()| self.params.clone()));
+                            if connected {
+                                return Ok(());
+                            }
+                        }
+                        Ok(DeviceControl::Update { params, reply }) => {
+                            let res = self.switch_params(*params, false).await;
+                            let connected = res.is_ok() && self.client.is_connected();
+                            let _ = reply.send(res.map(|()| self.params.clone()));
+                            if connected {
+                                return Ok(());
+                            }
+                        }
+                        Ok(DeviceControl::Reconnect { reply }) => {
+                            // retry now, answering with the result
+                            reconnect_reply = Some(reply);
+                            delay = RECONNECT_MIN_DELAY;
+                            break;
+                        }
+                        Ok(DeviceControl::Status { reply }) => {
+                            let _ = reply.send(self.status.clone());
+                        }
+                        Ok(DeviceControl::SubDeviceCreated { device_id, device }) => {
+                            self.params.sub_devices.insert(device_id, device);
+                        }
+                        Ok(DeviceControl::Shutdown { reply }) => {
+                            self.stopping = true;
+                            let _ = reply.send(());
+                            return Ok(());
+                        }
+                        Err(_) => return Ok(()),
+                    },
+                }
+            }
+
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        }
+    }
+
+    /// Register entities, subscribe and process messages until the connection drops
+    async fn run_session(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        in_rx: &kanal::AsyncReceiver<DeviceControl>,
+    ) -> Result<(), DeviceError> {
+        if !self.client.is_connected() {
+            return Err(ClientError::NotConnected.into());
+        }
+
+        // name, area, etc. may have changed since last connection
+        if let Some(info) = &self.info {
+            let metadata = DeviceMetadata::new(self.id, &self.host(), info);
+            custom::send(igloo_tx, custom::DEVICE_METADATA, &metadata).await?;
+            self.publish_sub_devices(igloo_tx).await?;
         }
 
         // publish entities
-        self.register_entities(&igloo_tx).await?; // TODO timeout, then crash
+        let list_timeout = self.params.timeouts.list_entities;
+        tokio::time::timeout(list_timeout, self.register_entities(igloo_tx))
+            .await
+            .map_err(|_| DeviceError::ListEntitiesTimeout(list_timeout))??;
+
+        #[cfg(feature = "voice")]
+        if self
+            .info
+            .as_ref()
+            .is_some_and(entity::voice_assistant::is_satellite)
+        {
+            let config = self.voice_assistant_config().await?;
+            self.publish_voice_config(igloo_tx, config).await?;
+        }
 
         self.subscribe_states().await?;
 
+        self.status.connected = true;
+        self.status.last_seen = Some(SystemTime::now());
+        self.publish_status(igloo_tx).await?;
+
+        // anything that arrived during setup
+        while let Some((msg_type, msg)) = self.client.pop_backlog() {
+            if let Err(e) = self.process_msg(igloo_tx, msg_type, msg).await {
+                if e.is_shutdown_request() {
+                    return Ok(());
+                }
+                self.report(igloo_tx, e).await;
+            }
+        }
+
+        let mut keepalive = interval(KEEPALIVE_INTERVAL);
+        keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        self.ping_sent = None;
+
         loop {
             tokio::select! {
-                Ok((eidx, comps)) = in_rx.recv() => {
-                    self.process_igloo_write(eidx, comps).await?;
+                Ok(control) = in_rx.recv() => match control {
+                    DeviceControl::Write(did, eidx, comps) => {
+                        // a bad write doesn't end the session, a dead connection does
+                        match self.process_igloo_write(did, eidx, comps).await {
+                            Err(e) if e.is_connection_lost() => return Err(e),
+                            Err(e) => self.report(igloo_tx, e).await,
+                            Ok(()) => {}
+                        }
+                    }
+                    DeviceControl::SetNoisePsk { psk, reply } => {
+                        let res = self.set_noise_psk(psk).await;
+                        let _ = reply.send(res.map(|()| self.params.clone()));
+                        // restart on the new connection, or reconnect with the old key
+                        return Ok(());
+                    }
+                    DeviceControl::Update { params, reply } => {
+                        let res = self.switch_params(*params, false).await;
+                        let _ = reply.send(res.map(|()| self.params.clone()));
+                        return Ok(());
+                    }
+                    DeviceControl::Reconnect { reply } => {
+                        let _ = self.force_disconnect().await;
+                        let res = self.connect().await.map(|_| ());
+                        if res.is_ok() {
+                            self.status.reconnects += 1;
+                        } else {
+                            let _ = self.client.force_disconnect().await;
+                        }
+                        let _ = reply.send(res);
+                        return Ok(());
+                    }
+                    DeviceControl::Status { reply } => {
+                        let _ = reply.send(self.status.clone());
+                    }
+                    DeviceControl::SubDeviceCreated { device_id, device } => {
+                        self.params.sub_devices.insert(device_id, device);
+                    }
+                    DeviceControl::Shutdown { reply } => {
+                        if self.disconnect().await.is_err() {
+                            let _ = self.force_disconnect().await;
+                        }
+                        self.stopping = true;
+                        let _ = reply.send(());
+                        return Ok(());
+                    }
                 },
 
-                result = self.connection.recv_msg() => {
-                    match result {
-                        Ok((msg_type, msg)) => {
-                            if let Err(e) = self.process_msg(&igloo_tx, msg_type, msg).await {
-                                eprintln!("[Device] Error processing message: {:?}", e);
-                                if matches!(e, DeviceError::DeviceRequestShutdown) {
-                                    break;
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("[Device] Error receiving message: {:?}", e);
+                _ = keepalive.tick() => {
+                    self.keepalive().await?;
+                },
+
+                // cancellation safe, frames are read by the connection's reader task
+                result = self.client.recv_msg() => {
+                    let (msg_type, msg) = result?;
+                    self.status.last_seen = Some(SystemTime::now());
+                    if let Err(e) = self.process_msg(igloo_tx, msg_type, msg).await {
+                        if e.is_shutdown_request() {
+                            return Ok(());
                         }
+                        self.report(igloo_tx, e).await;
                     }
                 }
             }
         }
+    }
 
-        Ok(())
+    /// Report a failure on this device to Igloo
+    async fn report(&self, igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>, e: DeviceError) {
+        custom::report(igloo_tx, ErrorReport::from(e).device(self.id)).await;
     }
 
-    pub async fn connect(&mut self) -> Result<api::DeviceInfoResponse, DeviceError> {
-        if self.connected {
-            unreachable!();
+    /// Feed a captured message from the device through the same handling
+    /// as a live one. Requests the device makes of us are skipped, there's
+    /// nothing to answer them on.
+    pub async fn replay_msg(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        msg_type: MessageType,
+        msg: BytesMut,
+    ) -> Result<(), DeviceError> {
+        self.client.update_state(&msg_type, &msg);
+        match msg_type {
+            MessageType::HelloResponse => {
+                let hello: api::HelloResponse = prost::Message::decode(msg)?;
+                self.client.set_hello(hello);
+            }
+            MessageType::DeviceInfoResponse => {
+                self.info = Some(prost::Message::decode(msg)?);
+            }
+            MessageType::ListEntitiesDoneResponse
+            | MessageType::PingRequest
+            | MessageType::GetTimeRequest
+            | MessageType::DisconnectRequest => {}
+            _ => {
+                if let Some((msg_type, msg)) = self.register_listed(igloo_tx, msg_type, msg).await?
+                {
+                    self.process_msg(igloo_tx, msg_type, msg).await?;
+                }
+            }
         }
+        Ok(())
+    }
 
-        // TODO fixme drop unexpected messages instead of failing
+    /// Next message from the device, answering its pings etc. on the way.
+    /// For driving a connected device by hand instead of with [`Device::run`].
+    pub async fn next_msg(&mut self) -> Result<(MessageType, BytesMut), DeviceError> {
+        Ok(self.client.next_msg().await?)
+    }
 
-        self.connection.connect().await?;
+    /// What the device reported in its `HelloResponse`
+    pub fn api_version(&self) -> ApiVersion {
+        self.client.api_version()
+    }
 
-        let _: api::HelloResponse = self
-            .trans_msg(
-                MessageType::HelloRequest,
-                &api::HelloRequest {
-                    client_info: "igloo-esphome".to_string(),
-                    api_version_major: 1,
-                    api_version_minor: 9,
-                },
-                MessageType::HelloResponse,
-            )
-            .await?;
+    pub fn server_info(&self) -> &str {
+        self.client.server_info()
+    }
 
-        let res = self
-            .trans_msg::<api::ConnectResponse>(
-                MessageType::ConnectRequest,
-                &api::ConnectRequest {
-                    password: self.password.clone(),
-                },
-                MessageType::ConnectResponse,
-            )
-            .await;
+    /// Span every log of this device (and its connection) belongs to
+    pub fn span(&self) -> Span {
+        info_span!("device", id = self.id, name = %self.display_name())
+    }
 
-        if let Ok(msg) = res
-            && msg.invalid_password
-        {
-            return Err(DeviceError::InvalidPassword);
+    /// Name shown in Igloo
+    pub fn display_name(&self) -> &str {
+        match (&self.info, &self.params.name) {
+            (Some(info), _) => custom::display_name(info),
+            (None, Some(name)) => name,
+            (None, None) => &self.params.ip,
         }
+    }
 
-        self.connected = true;
+    /// Igloo device an ESPHome (sub-)device's entities go on
+    fn igloo_device(&self, device_id: u32) -> u64 {
+        match device_id {
+            0 => self.id,
+            _ => self
+                .params
+                .sub_devices
+                .get(&device_id)
+                .copied()
+                .unwrap_or(self.id),
+        }
+    }
 
-        self.device_info().await
+    /// Name of an ESPHome (sub-)device, entity names are shown relative to it
+    fn device_name(&self, device_id: u32) -> &str {
+        let sub = self.info.as_ref().and_then(|info| {
+            info.devices
+                .iter()
+                .find(|sub| sub.device_id == device_id && sub.device_id != 0)
+        });
+        match sub {
+            Some(sub) if self.igloo_device(device_id) != self.id => &sub.name,
+            _ => self.display_name(),
+        }
     }
 
-    async fn subscribe_states(&mut self) -> Result<(), DeviceError> {
-        self.send_msg(
-            MessageType::SubscribeStatesRequest,
-            &api::SubscribeStatesRequest {},
-        )
-        .await?;
+    /// Host part of [`ConnectionParams::ip`], IPv6 in brackets so it fits in a URL
+    pub fn host(&self) -> String {
+        let ip = &self.params.ip;
+        let addr = ip
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .or_else(|_| ip.parse::<IpAddr>());
+        match addr {
+            Ok(IpAddr::V6(addr)) => format!("[{addr}]"),
+            Ok(addr) => addr.to_string(),
+            // hostname, with or without a port
+            Err(_) => match ip.rsplit_once(':') {
+                Some((host, _port)) => host.to_string(),
+                None => ip.clone(),
+            },
+        }
+    }
 
+    /// Send a ping, failing if the last one was never answered
+    async fn keepalive(&mut self) -> Result<(), DeviceError> {
+        match self.ping_sent {
+            Some(sent) if sent.elapsed() > KEEPALIVE_TIMEOUT => {
+                Err(DeviceError::PingTimeout(KEEPALIVE_TIMEOUT))
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.client.send(&api::PingRequest {}).await?;
+                self.ping_sent = Some(Instant::now());
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn connect(&mut self) -> Result<api::DeviceInfoResponse, DeviceError> {
+        self.client.connect().await?;
+        let info = self.device_info().await?;
+        self.info = Some(info.clone());
+        Ok(info)
+    }
+
+    async fn subscribe_states(&mut self) -> Result<(), DeviceError> {
+        self.client.send(&api::SubscribeStatesRequest {}).await?;
         Ok(())
     }
 
     /// Send disconnect request to device, wait for response, then disconnect socket
     pub async fn disconnect(&mut self) -> Result<(), DeviceError> {
-        let _: api::DisconnectResponse = self
-            .trans_msg(
-                MessageType::DisconnectRequest,
-                &api::DisconnectRequest {},
-                MessageType::DisconnectResponse,
-            )
-            .await?;
-        self.force_disconnect().await
+        Ok(self.client.disconnect().await?)
     }
 
     /// Disconnect socket (without sending disconnect request to device)
     pub async fn force_disconnect(&mut self) -> Result<(), DeviceError> {
-        self.connection.disconnect().await?;
-        self.connected = false;
-        Ok(())
+        Ok(self.client.force_disconnect().await?)
     }
 
-    pub async fn device_info(&mut self) -> Result<api::DeviceInfoResponse, DeviceError> {
-        let res: api::DeviceInfoResponse = self
-            .trans_msg(
-                MessageType::DeviceInfoRequest,
-                &api::DeviceInfoRequest {},
-                MessageType::DeviceInfoResponse,
-            )
-            .await?;
-        Ok(res)
+    /// Switch to a new noise PSK (or plaintext with `None`), pushing it to
+    /// the device first when the firmware supports that. The new key is only
+    /// kept once a connection with it succeeds, otherwise the old one is restored.
+    /// Leaves the device connected on success.
+    async fn set_noise_psk(&mut self, psk: Option<Secret>) -> Result<(), DeviceError> {
+        let key = psk
+            .as_ref()
+            .map(|psk| noise::decode_noise_psk(psk.expose()))
+            .transpose()?;
+
+        let supported = self
+            .info
+            .as_ref()
+            .is_some_and(|info| info.api_encryption_supported);
+
+        // once the device takes the key, the old one is gone for good
+        let mut pushed = false;
+        if self.client.is_connected()
+            && supported
+            && let Some(key) = key
+        {
+            let res = self
+                .client
+                .request(&api::NoiseEncryptionSetKeyRequest { key: key.to_vec() })
+                .await?;
+            if !res.success {
+                return Err(DeviceError::NoisePskRejected);
+            }
+            pushed = true;
+        }
+
+        let params = ConnectionParams {
+            noise_psk: psk,
+            ..self.params.clone()
+        };
+        // reverting would lock us out once the device took the key
+        self.switch_params(params, pushed).await
     }
 
-    pub async fn send_msg(
+    /// Reconnect with new params. If that fails the old ones are restored,
+    /// unless `keep_on_failure` (the device already moved on to the new ones).
+    /// Leaves the device connected on success.
+    async fn switch_params(
         &mut self,
-        msg_type: MessageType,
-        msg: &impl prost::Message,
+        params: ConnectionParams,
+        keep_on_failure: bool,
     ) -> Result<(), DeviceError> {
-        let msg_len = msg.encoded_len();
-        let mut bytes = BytesMut::with_capacity(msg_len);
-        msg.encode(&mut bytes)?;
-        bytes.truncate(msg_len);
-        self.connection.send_msg(msg_type, &bytes).await?;
-        Ok(())
+        if self.client.is_connected() {
+            let _ = self.force_disconnect().await;
+        }
+
+        let old_params = std::mem::replace(&mut self.params, params);
+        // sub-devices belong to the node, not to how we reach it
+        self.params.sub_devices = old_params.sub_devices.clone();
+        self.apply_params();
+
+        match self.connect().await {
+            Ok(_) => Ok(()),
+            Err(e) if keep_on_failure => {
+                warn!("can't connect with the new params yet: {e}");
+                let _ = self.client.force_disconnect().await;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = self.client.force_disconnect().await;
+                self.params = old_params;
+                self.apply_params();
+                Err(e)
+            }
+        }
+    }
+
+    /// Rebuild the client from [`Device::params`]
+    fn apply_params(&mut self) {
+        self.client = Self::new_client(&self.params);
     }
 
-    async fn recv_msg<U: prost::Message + Default>(
+    pub async fn device_info(&mut self) -> Result<api::DeviceInfoResponse, DeviceError> {
+        Ok(self.client.request(&api::DeviceInfoRequest {}).await?)
+    }
+
+    #[cfg(feature = "voice")]
+    pub async fn voice_assistant_config(
         &mut self,
-        expected_msg_type: MessageType,
-    ) -> Result<U, DeviceError> {
-        let (msg_type, mut msg) = self.connection.recv_msg().await?;
-        // TODO maybe just skip?
-        if msg_type != expected_msg_type {
-            return Err(DeviceError::WrongMessageType(msg_type));
-        }
-        Ok(U::decode(&mut msg)?)
+    ) -> Result<api::VoiceAssistantConfigurationResponse, DeviceError> {
+        Ok(self
+            .client
+            .request(&api::VoiceAssistantConfigurationRequest {})
+            .await?)
     }
 
-    async fn trans_msg<U: prost::Message + Default>(
+    pub async fn send_msg(
         &mut self,
-        req_type: MessageType,
-        req: &impl prost::Message,
-        res_type: MessageType,
-    ) -> Result<U, DeviceError> {
-        self.send_msg(req_type, req).await?;
-        self.recv_msg(res_type).await
+        msg_type: MessageType,
+        msg: &impl prost::Message,
+    ) -> Result<(), DeviceError> {
+        Ok(self.client.send_msg(msg_type, msg).await?)
     }
 
     #[inline]
     async fn process_igloo_write(
         &mut self,
+        did: u64,
         eindex: usize,
         comps: Vec<Component>,
     ) -> Result<(), DeviceError> {
-        let Some((entity_type, key)) = self.entity_index_to_info.get(eindex) else {
-            eprintln!(
-                "Igloo send update for unknown entity {eindex} on device {}",
-                self.id
-            );
-            return Ok(());
+        let entity = self
+            .entity_index_to_info
+            .get(&did)
+            .and_then(|entities| entities.get(eindex));
+        let (entity_type, EntityKey { device_id, key }) = match entity {
+            Some(EntityRef::Esphome(entity_type, key)) => (entity_type.clone(), *key),
+            Some(EntityRef::Synthetic(entity)) => {
+                return match entity {
+                    #[cfg(feature = "voice")]
+                    SyntheticEntity::WakeWords | SyntheticEntity::WakeWord(_) => {
+                        entity::voice_assistant::process(self, entity.clone(), comps).await
+                    }
+                    SyntheticEntity::Status => {
+                        Err(DeviceError::UnsupportedCommand("status entity".to_string()))
+                    }
+                };
+            }
+            None => return Err(DeviceError::InvalidEntity(eindex)),
         };
 
         match entity_type {
-            EntityType::Light => entity::light::process(self, *key, comps).await,
-            EntityType::Switch => entity::switch::process(self, *key, comps).await,
-            EntityType::Button => entity::button::process(self, *key, comps).await,
-            EntityType::Number => entity::number::process(self, *key, comps).await,
-            EntityType::Select => entity::select::process(self, *key, comps).await,
-            EntityType::Text => entity::text::process(self, *key, comps).await,
-            EntityType::Fan => entity::fan::process(self, *key, comps).await,
-            EntityType::Cover => entity::cover::process(self, *key, comps).await,
-            EntityType::Valve => entity::valve::process(self, *key, comps).await,
-            EntityType::Siren => entity::siren::process(self, *key, comps).await,
-            EntityType::Lock => entity::lock::process(self, *key, comps).await,
-            EntityType::MediaPlayer => entity::media_player::process(self, *key, comps).await,
-            EntityType::Date => entity::date::process(self, *key, comps).await,
-            EntityType::Time => entity::time::process(self, *key, comps).await,
-            EntityType::DateTime => entity::date_time::process(self, *key, comps).await,
+            EntityType::Light => entity::light::process(self, key, device_id, comps).await,
+            EntityType::Switch => entity::switch::process(self, key, device_id, comps).await,
+            EntityType::Button => entity::button::process(self, key, device_id, comps).await,
+            EntityType::Number => entity::number::process(self, key, device_id, comps).await,
+            EntityType::Select => entity::select::process(self, key, device_id, comps).await,
+            EntityType::Text => entity::text::process(self, key, device_id, comps).await,
+            EntityType::Fan => entity::fan::process(self, key, device_id, comps).await,
+            EntityType::Cover => entity::cover::process(self, key, device_id, comps).await,
+            EntityType::Valve => entity::valve::process(self, key, device_id, comps).await,
+            EntityType::Siren => entity::siren::process(self, key, device_id, comps).await,
+            EntityType::Lock => entity::lock::process(self, key, device_id, comps).await,
+            #[cfg(feature = "media")]
+            EntityType::MediaPlayer => {
+                entity::media_player::process(self, key, device_id, comps).await
+            }
+            EntityType::Date => entity::date::process(self, key, device_id, comps).await,
+            EntityType::Time => entity::time::process(self, key, device_id, comps).await,
+            EntityType::DateTime => entity::date_time::process(self, key, device_id, comps).await,
             EntityType::AlarmControlPanel => {
-                entity::alarm_control_panel::process(self, *key, comps).await
+                entity::alarm_control_panel::process(self, key, device_id, comps).await
             }
-            EntityType::Update => entity::update::process(self, *key, comps).await,
-            EntityType::Climate => entity::climate::process(self, *key, comps).await,
+            EntityType::Update => entity::update::process(self, key, device_id, comps).await,
+            EntityType::Climate => entity::climate::process(self, key, device_id, comps).await,
 
-            _ => {
-                eprintln!("{entity_type:#?} currently does not support commands. Skipping..");
-                Ok(())
-            }
+            _ => Err(DeviceError::UnsupportedCommand(format!("{entity_type:?}"))),
         }
     }
 
+    /// Whether `msg_type` has a use here or in the [`Client`], or is
+    /// deliberately dropped
+    pub fn handles(msg_type: &MessageType) -> bool {
+        Client::handles(msg_type)
+            || Self::lists(msg_type)
+            || Self::dispatches(msg_type)
+            || is_ignored(msg_type)
+            || matches!(
+                msg_type,
+                MessageType::DeviceInfoResponse
+                    | MessageType::ListEntitiesDoneResponse
+                    | MessageType::PingResponse
+                    | MessageType::SubscribeLogsResponse
+                    | MessageType::NoiseEncryptionSetKeyResponse
+            )
+            || cfg!(feature = "voice")
+                && *msg_type == MessageType::VoiceAssistantConfigurationResponse
+    }
+
     #[inline]
     async fn process_msg(
         &mut self,
@@ -316,33 +890,16 @@ impl Device {
         msg_type: MessageType,
         msg: BytesMut,
     ) -> Result<(), DeviceError> {
+        if self.client.answer_request(&msg_type).await? {
+            return Ok(());
+        }
+
         match msg_type {
-            MessageType::DisconnectRequest => {
-                self.send_msg(MessageType::DisconnectResponse, &api::DisconnectResponse {})
-                    .await?;
-                self.connection.disconnect().await?;
-                return Err(DeviceError::DeviceRequestShutdown);
-            }
-            MessageType::PingRequest => {
-                self.send_msg(MessageType::PingResponse, &api::PingResponse {})
-                    .await?;
-            }
             MessageType::PingResponse => {
-                self.last_ping = Some(SystemTime::now());
-            }
-            MessageType::GetTimeRequest => {
-                self.send_msg(
-                    MessageType::GetTimeResponse,
-                    &api::GetTimeResponse {
-                        epoch_seconds: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .map_err(DeviceError::SystemTimeError)?
-                            .as_secs()
-                            .try_into()
-                            .map_err(DeviceError::SystemTimeIntCastError)?,
-                    },
-                )
-                .await?;
+                if let Some(sent) = self.ping_sent.take() {
+                    self.status.ping_latency = Some(sent.elapsed());
+                    self.publish_status(igloo_tx).await?;
+                }
             }
             MessageType::SubscribeLogsResponse => {
                 // TODO how should logs work?
@@ -350,111 +907,17 @@ impl Device {
                 // for this device and it starts collecting logs to file?
                 // Maybe it collects in ram, then has a custom
             }
-
-            _ => {
-                self.process_state_update(igloo_tx, msg_type, msg).await?;
+            #[cfg(feature = "voice")]
+            MessageType::VoiceAssistantConfigurationResponse => {
+                let config: api::VoiceAssistantConfigurationResponse = prost::Message::decode(msg)?;
+                self.publish_voice_config(igloo_tx, config).await?;
             }
-        }
-        Ok(())
-    }
 
-    #[inline]
-    pub async fn process_state_update(
-        &mut self,
-        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
-        msg_type: MessageType,
-        msg: BytesMut,
-    ) -> Result<(), DeviceError> {
-        match msg_type {
-            MessageType::DisconnectRequest
-            | MessageType::PingRequest
-            | MessageType::PingResponse
-            | MessageType::GetTimeRequest
-            | MessageType::SubscribeLogsResponse => unreachable!(),
-            MessageType::BinarySensorStateResponse => {
-                self.apply_entity_update(igloo_tx, api::BinarySensorStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::CoverStateResponse => {
-                self.apply_entity_update(igloo_tx, api::CoverStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::FanStateResponse => {
-                self.apply_entity_update(igloo_tx, api::FanStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::LightStateResponse => {
-                self.apply_entity_update(igloo_tx, api::LightStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::SensorStateResponse => {
-                self.apply_entity_update(igloo_tx, api::SensorStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::SwitchStateResponse => {
-                self.apply_entity_update(igloo_tx, api::SwitchStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::TextSensorStateResponse => {
-                self.apply_entity_update(igloo_tx, api::TextSensorStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::ClimateStateResponse => {
-                self.apply_entity_update(igloo_tx, api::ClimateStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::NumberStateResponse => {
-                self.apply_entity_update(igloo_tx, api::NumberStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::SelectStateResponse => {
-                self.apply_entity_update(igloo_tx, api::SelectStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::SirenStateResponse => {
-                self.apply_entity_update(igloo_tx, api::SirenStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::LockStateResponse => {
-                self.apply_entity_update(igloo_tx, api::LockStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::MediaPlayerStateResponse => {
-                self.apply_entity_update(igloo_tx, api::MediaPlayerStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::AlarmControlPanelStateResponse => {
-                self.apply_entity_update(
-                    igloo_tx,
-                    api::AlarmControlPanelStateResponse::decode(msg)?,
-                )
-                .await?;
-            }
-            MessageType::TextStateResponse => {
-                self.apply_entity_update(igloo_tx, api::TextStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::DateStateResponse => {
-                self.apply_entity_update(igloo_tx, api::DateStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::TimeStateResponse => {
-                self.apply_entity_update(igloo_tx, api::TimeStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::ValveStateResponse => {
-                self.apply_entity_update(igloo_tx, api::ValveStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::DateTimeStateResponse => {
-                self.apply_entity_update(igloo_tx, api::DateTimeStateResponse::decode(msg)?)
-                    .await?;
-            }
-            MessageType::UpdateStateResponse => {
-                self.apply_entity_update(igloo_tx, api::UpdateStateResponse::decode(msg)?)
-                    .await?;
+            _ if is_ignored(&msg_type) => {}
+            _ if Self::dispatches(&msg_type) => {
+                self.process_state_update(igloo_tx, msg_type, msg).await?;
             }
-            _ => {}
+            _ => debug!(%msg_type, "no handling for message, dropping it"),
         }
         Ok(())
     }
@@ -468,13 +931,19 @@ impl Device {
             return Ok(());
         }
 
-        let Some(entity) = self.entity_key_to_index.get(&update.key()) else {
-            // TODO log err - update for unknown entity
+        let key = update.entity_key();
+        if self.disabled_keys.contains(&key) {
+            return Ok(());
+        }
+
+        let Some(&(device, entity)) = self.entity_key_to_index.get(&key) else {
+            debug!(?key, "state update for unknown entity");
             return Ok(());
         };
+        trace!(?key, device, entity, "state update");
 
         igloo_tx
-            .write_components(self.id, *entity, update.comps())
+            .write_components(device, entity, update.comps())
             .await?;
 
         Ok(())
@@ -484,300 +953,373 @@ impl Device {
         &mut self,
         igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
     ) -> Result<(), DeviceError> {
-        self.send_msg(
-            MessageType::ListEntitiesRequest,
-            &api::ListEntitiesRequest {},
-        )
-        .await?;
+        self.client.send(&api::ListEntitiesRequest {}).await?;
 
         loop {
-            let (msg_type, msg) = self.connection.recv_msg().await?;
-            match msg_type {
-                MessageType::ListEntitiesServicesResponse => {
-                    continue;
-                }
-                MessageType::ListEntitiesDoneResponse => break,
-                MessageType::ListEntitiesBinarySensorResponse => {
-                    let msg = api::ListEntitiesBinarySensorResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::BinarySensor,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesCoverResponse => {
-                    let msg = api::ListEntitiesCoverResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Cover,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesFanResponse => {
-                    let msg = api::ListEntitiesFanResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Fan,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesLightResponse => {
-                    let msg = api::ListEntitiesLightResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Light,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesSensorResponse => {
-                    let msg = api::ListEntitiesSensorResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Sensor,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesSwitchResponse => {
-                    let msg = api::ListEntitiesSwitchResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Switch,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesTextSensorResponse => {
-                    let msg = api::ListEntitiesTextSensorResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::TextSensor,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesCameraResponse => {
-                    let msg = api::ListEntitiesCameraResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Camera,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesClimateResponse => {
-                    let msg = api::ListEntitiesClimateResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Climate,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesNumberResponse => {
-                    let msg = api::ListEntitiesNumberResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Number,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesSelectResponse => {
-                    let msg = api::ListEntitiesSelectResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Select,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesSirenResponse => {
-                    let msg = api::ListEntitiesSirenResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Siren,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesLockResponse => {
-                    let msg = api::ListEntitiesLockResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Lock,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesButtonResponse => {
-                    let msg = api::ListEntitiesButtonResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Button,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesMediaPlayerResponse => {
-                    let msg = api::ListEntitiesMediaPlayerResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::MediaPlayer,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesAlarmControlPanelResponse => {
-                    let msg = api::ListEntitiesAlarmControlPanelResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::AlarmControlPanel,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesTextResponse => {
-                    let msg = api::ListEntitiesTextResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Text,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesDateResponse => {
-                    let msg = api::ListEntitiesDateResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Date,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesTimeResponse => {
-                    let msg = api::ListEntitiesTimeResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Time,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesEventResponse => {
-                    let msg = api::ListEntitiesEventResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Event,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesValveResponse => {
-                    let msg = api::ListEntitiesValveResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Valve,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesDateTimeResponse => {
-                    let msg = api::ListEntitiesDateTimeResponse::decode(msg)?;
-                    self.register_entity(
-                        igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::DateTime,
-                        msg.comps(),
-                    )
-                    .await?;
-                }
-                MessageType::ListEntitiesUpdateResponse => {
-                    let msg = api::ListEntitiesUpdateResponse::decode(msg)?;
-                    self.register_entity(
+            let (msg_type, msg) = self.client.recv_msg().await?;
+            if msg_type == MessageType::ListEntitiesDoneResponse {
+                break;
+            }
+            if let Some((msg_type, msg)) = self.register_listed(igloo_tx, msg_type, msg).await? {
+                self.client.handle_unsolicited(msg_type, msg).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Register an entity (if not disabled) and write its initial components
+    pub async fn register_entity<T: EntityRegister>(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        entity_type: EntityType,
+        msg: T,
+    ) -> Result<Option<usize>, DeviceError> {
+        let key = msg.entity_key();
+        // already registered before reconnecting
+        let registered = self.entity_key_to_index.get(&key).copied();
+        let device = match registered {
+            Some((device, _)) => device,
+            None => self.igloo_device(key.device_id),
+        };
+        let entity_id = self
+            .entity_ids
+            .entry(device)
+            .or_default()
+            .assign(&entity_type, &msg);
+
+        if self.is_disabled(&entity_id, msg.disabled_by_default()) {
+            debug!(?key, entity_id, "skipping disabled entity");
+            self.disabled_keys.insert(key);
+            return Ok(None);
+        }
+        self.disabled_keys.remove(&key);
+
+        let entity_index = match registered {
+            Some((_, entity_index)) => entity_index,
+            None => {
+                let entity_index = self
+                    .add_entity(
                         igloo_tx,
-                        msg.name.to_string(),
-                        msg.key,
-                        EntityType::Update,
-                        msg.comps(),
+                        device,
+                        entity_id,
+                        EntityRef::Esphome(entity_type, key),
                     )
                     .await?;
-                }
-                _ => continue,
+                self.entity_key_to_index.insert(key, (device, entity_index));
+                entity_index
             }
+        };
+
+        let metadata = EntityMetadata {
+            device,
+            entity: entity_index,
+            name: identity::display_name(&msg, self.device_name(key.device_id)),
+        };
+        custom::send(igloo_tx, custom::ENTITY_METADATA, &metadata).await?;
+
+        igloo_tx
+            .write_components(device, entity_index, msg.comps_on(self.api_version()))
+            .await?;
+
+        Ok(Some(entity_index))
+    }
+
+    /// Config overrides win over the firmware's `disabled_by_default`
+    fn is_disabled(&self, entity_id: &str, disabled_by_default: bool) -> bool {
+        if self
+            .params
+            .disable_entities
+            .iter()
+            .any(|id| id == entity_id)
+        {
+            return true;
         }
-        Ok(())
+        if self.params.enable_entities.iter().any(|id| id == entity_id) {
+            return false;
+        }
+        disabled_by_default
     }
 
-    pub async fn register_entity(
+    /// Write a synthetic entity's components, registering it first if needed
+    pub async fn write_synthetic(
         &mut self,
         igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
         entity_id: String,
-        key: u32,
-        entity_type: EntityType,
+        entity: SyntheticEntity,
         comps: Vec<Component>,
     ) -> Result<usize, DeviceError> {
-        let entity_index = self.next_entity_index;
+        let entity_index = match self.synthetic_to_index.get(&entity) {
+            Some(entity_index) => *entity_index,
+            None => {
+                self.entity_ids
+                    .entry(self.id)
+                    .or_default()
+                    .reserve(&entity_id);
+                let entity_index = self
+                    .add_entity(
+                        igloo_tx,
+                        self.id,
+                        entity_id,
+                        EntityRef::Synthetic(entity.clone()),
+                    )
+                    .await?;
+                self.synthetic_to_index.insert(entity, entity_index);
+                entity_index
+            }
+        };
 
         igloo_tx
-            .register_entity(self.id, entity_id, entity_index)
+            .write_components(self.id, entity_index, comps)
             .await?;
 
-        self.entity_key_to_index.insert(key, self.next_entity_index);
-        self.entity_index_to_info.push((entity_type, key));
-        self.next_entity_index += 1;
+        Ok(entity_index)
+    }
+
+    async fn add_entity(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        device: u64,
+        entity_id: String,
+        entity: EntityRef,
+    ) -> Result<usize, DeviceError> {
+        let entities = self.entity_index_to_info.entry(device).or_default();
+        let entity_index = entities.len();
 
         igloo_tx
-            .write_components(self.id, entity_index, comps)
+            .register_entity(device, entity_id, entity_index)
             .await?;
 
+        entities.push(entity);
+
         Ok(entity_index)
     }
+
+    /// Create an Igloo device for every sub-device the node declares, then
+    /// (re)send their metadata
+    async fn publish_sub_devices(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+    ) -> Result<(), DeviceError> {
+        let Some(info) = self.info.clone() else {
+            return Ok(());
+        };
+
+        for sub in &info.devices {
+            if sub.device_id == 0 {
+                continue;
+            }
+            let device = match self.params.sub_devices.get(&sub.device_id) {
+                Some(device) => *device,
+                None => match self.create_sub_device(igloo_tx, &info, sub).await? {
+                    Some(device) => device,
+                    None => continue,
+                },
+            };
+            let metadata = DeviceMetadata::sub_device(device, self.id, &self.host(), &info, sub);
+            custom::send(igloo_tx, custom::DEVICE_METADATA, &metadata).await?;
+        }
+        Ok(())
+    }
+
+    /// Ask Igloo for a device for a sub-device. `None` if it can't be made now,
+    /// its entities then go on the node's device until the next connection.
+    async fn create_sub_device(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        info: &api::DeviceInfoResponse,
+        sub: &api::DeviceInfo,
+    ) -> Result<Option<u64>, DeviceError> {
+        let Some(pending) = &self.pending_sub_devices else {
+            return Ok(None);
+        };
+
+        // Igloo answers by name alone, so it carries the node and sub-device
+        // IDs to stay unique. DEVICE_METADATA gives the proper name.
+        let name = format!(
+            "{} {} ({}/{})",
+            custom::display_name(info),
+            sub.name,
+            self.id,
+            sub.device_id
+        );
+        let (reply, reply_rx) = oneshot::channel();
+        let pending_sub = PendingSubDevice {
+            node: self.id,
+            device_id: sub.device_id,
+            reply,
+        };
+        // still asked from an earlier connection, don't make a second device
+        let asked = pending
+            .lock()
+            .await
+            .insert(name.clone(), pending_sub)
+            .is_some();
+        if !asked && let Err(e) = igloo_tx.create_device(name.clone()).await {
+            pending.lock().await.remove(&name);
+            return Err(e.into());
+        }
+
+        match tokio::time::timeout(SUB_DEVICE_TIMEOUT, reply_rx).await {
+            Ok(Ok(device)) => {
+                info!(device_id = sub.device_id, device, "created sub-device");
+                self.params.sub_devices.insert(sub.device_id, device);
+                Ok(Some(device))
+            }
+            _ => {
+                // stays pending, main records the device whenever it shows up
+                warn!(
+                    device_id = sub.device_id,
+                    "Igloo hasn't created the sub-device yet"
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    async fn publish_status(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+    ) -> Result<(), DeviceError> {
+        let comps = entity::status::comps(
+            &self.status,
+            self.info.as_ref(),
+            self.client.api_version(),
+            self.client.server_info(),
+        );
+        self.write_synthetic(
+            igloo_tx,
+            "status".to_string(),
+            SyntheticEntity::Status,
+            comps,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Publish the wake word multi-select and its options
+    #[cfg(feature = "voice")]
+    async fn publish_voice_config(
+        &mut self,
+        igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+        config: api::VoiceAssistantConfigurationResponse,
+    ) -> Result<(), DeviceError> {
+        use entity::voice_assistant::{wake_word_comps, wake_words_comps};
+
+        self.write_synthetic(
+            igloo_tx,
+            "wake_words".to_string(),
+            SyntheticEntity::WakeWords,
+            wake_words_comps(&config),
+        )
+        .await?;
+
+        for wake_word in &config.available_wake_words {
+            self.write_synthetic(
+                igloo_tx,
+                format!("wake_word_{}", wake_word.id),
+                SyntheticEntity::WakeWord(wake_word.id.clone()),
+                wake_word_comps(&config, wake_word),
+            )
+            .await?;
+        }
+
+        self.voice_config = Some(config);
+        Ok(())
+    }
+}
+
+/// `Device::register_listed`, `Device::process_state_update` and
+/// `Device::dispatches`, with one arm per entity type
+macro_rules! entity_dispatch {
+    (
+        listed { $($(#[$list_cfg:meta])* $entity:ident => $list:ident,)* }
+        updates { $($(#[$update_cfg:meta])* $update_entity:ident => $update:ident,)* }
+    ) => {
+        impl Device {
+            /// Register the entity from a `ListEntities*Response`.
+            /// Hands back anything else.
+            async fn register_listed(
+                &mut self,
+                igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+                msg_type: MessageType,
+                msg: BytesMut,
+            ) -> Result<Option<(MessageType, BytesMut)>, DeviceError> {
+                if !Self::lists(&msg_type) {
+                    return Ok(Some((msg_type, msg)));
+                }
+                match decode_message(&msg_type, &msg)? {
+                    $(
+                        $(#[$list_cfg])*
+                        Some(AnyMessage::$list(listed)) => {
+                            self.register_entity(igloo_tx, EntityType::$entity, listed).await?;
+                        }
+                    )*
+                    // user defined services, not entities
+                    _ => {}
+                }
+                Ok(None)
+            }
+
+            /// Forward an entity's state update to Igloo, ignoring anything else
+            pub async fn process_state_update(
+                &mut self,
+                igloo_tx: &kanal::AsyncSender<ExtensionToIgloo>,
+                msg_type: MessageType,
+                msg: BytesMut,
+            ) -> Result<(), DeviceError> {
+                if !Self::dispatches(&msg_type) {
+                    return Ok(());
+                }
+                match decode_message(&msg_type, &msg)? {
+                    $(
+                        $(#[$update_cfg])*
+                        Some(AnyMessage::$update(update)) => {
+                            self.apply_entity_update(igloo_tx, update).await?;
+                        }
+                    )*
+                    _ => {}
+                }
+                Ok(())
+            }
+
+            /// Whether `msg_type` is handled by [`Device::register_listed`]
+            fn lists(msg_type: &MessageType) -> bool {
+                match msg_type {
+                    MessageType::ListEntitiesServicesResponse => true,
+                    $($(#[$list_cfg])* MessageType::$list => true,)*
+                    _ => false,
+                }
+            }
+
+            /// Whether `msg_type` is an entity listing or update handled above
+            fn dispatches(msg_type: &MessageType) -> bool {
+                match msg_type {
+                    $($(#[$list_cfg])* MessageType::$list => true,)*
+                    $($(#[$update_cfg])* MessageType::$update => true,)*
+                    _ => false,
+                }
+            }
+        }
+    };
+}
+
+esphome_client::entity_messages!(entity_dispatch);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Catches messages added by a newly vendored api.proto
+    #[test]
+    fn every_device_message_is_handled() {
+        let unhandled: Vec<_> = (0..=u16::MAX)
+            .filter_map(MessageType::from_repr)
+            .filter(|msg_type| msg_type.from_device() && msg_type.is_enabled())
+            .filter(|msg_type| !Device::handles(msg_type))
+            .collect();
+        assert!(
+            unhandled.is_empty(),
+            "no handling for {unhandled:?}, handle them or add them to `IGNORED_MESSAGES`"
+        );
+    }
 }
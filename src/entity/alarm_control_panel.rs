@@ -1,11 +1,12 @@
-use igloo_interface::{Component, AlarmState};
-use super::{EntityRegister, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_entity_category, add_icon};
 use crate::{
     api,
     device::{Device, DeviceError},
     entity::EntityUpdate,
     model::MessageType,
 };
+use igloo_interface::{AlarmState, Component};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesAlarmControlPanelResponse {
     fn comps(self) -> Vec<Component> {
@@ -16,8 +17,10 @@ impl EntityRegister for api::ListEntitiesAlarmControlPanelResponse {
     }
 }
 
-impl api::AlarmControlPanelState {
-    pub fn as_igloo(&self) -> AlarmState {
+impl AsIgloo for api::AlarmControlPanelState {
+    type Igloo = AlarmState;
+
+    fn as_igloo(&self) -> AlarmState {
         match self {
             api::AlarmControlPanelState::AlarmStateDisarmed => AlarmState::Disarmed,
             api::AlarmControlPanelState::AlarmStateArmedHome => AlarmState::ArmedHome,
@@ -34,10 +37,6 @@ impl api::AlarmControlPanelState {
 }
 
 impl EntityUpdate for api::AlarmControlPanelStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         vec![Component::AlarmState(self.state().as_igloo())]
     }
@@ -65,10 +64,12 @@ fn alarm_state_to_command(state: &AlarmState) -> api::AlarmControlPanelStateComm
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::AlarmControlPanelCommandRequest {
         key,
+        device_id,
         command: api::AlarmControlPanelStateCommand::AlarmControlPanelDisarm.into(),
         code: String::new(),
     };
@@ -85,8 +86,10 @@ pub async fn process(
             }
 
             comp => {
-                println!(
-                    "AlarmControlPanel got unexpected component '{comp:?}' during transaction. Skipping.."
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected alarm control panel component during transaction, skipping"
                 );
             }
         }
@@ -1,6 +1,6 @@
+use super::{EntityRegister, add_device_class, add_entity_category, add_icon};
 use crate::{api, entity::EntityUpdate};
 use igloo_interface::Component;
-use super::{EntityRegister, add_device_class, add_entity_category, add_icon};
 
 impl EntityRegister for api::ListEntitiesBinarySensorResponse {
     fn comps(self) -> Vec<Component> {
@@ -14,10 +14,6 @@ impl EntityRegister for api::ListEntitiesBinarySensorResponse {
 }
 
 impl EntityUpdate for api::BinarySensorStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -5,6 +5,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesButtonResponse {
     fn comps(self) -> Vec<Component> {
@@ -20,12 +21,17 @@ impl EntityRegister for api::ListEntitiesButtonResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    let req = api::ButtonCommandRequest { key };
+    let req = api::ButtonCommandRequest { key, device_id };
 
     for comp in comps {
-        println!("Button got unexpected component '{comp:?}' during transaction. Skipping..");
+        warn!(
+            key,
+            ?comp,
+            "unexpected button component during transaction, skipping"
+        );
     }
 
     device
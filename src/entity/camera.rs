@@ -1,5 +1,5 @@
 use super::{EntityRegister, add_entity_category, add_icon};
-use crate::api;
+use crate::{api, entity::EntityUpdate};
 use igloo_interface::Component;
 
 impl EntityRegister for api::ListEntitiesCameraResponse {
@@ -10,3 +10,14 @@ impl EntityRegister for api::ListEntitiesCameraResponse {
         comps
     }
 }
+
+impl EntityUpdate for api::CameraImageResponse {
+    // Igloo has no image component yet, so frames are dropped
+    fn should_skip(&self) -> bool {
+        true
+    }
+
+    fn comps(&self) -> Vec<Component> {
+        Vec::new()
+    }
+}
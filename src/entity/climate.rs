@@ -1,11 +1,12 @@
-use igloo_interface::{Component, ClimateMode, FanOscillation, FanSpeed};
-use super::{EntityRegister, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_entity_category, add_icon};
 use crate::{
     api,
     device::{Device, DeviceError},
     entity::EntityUpdate,
     model::MessageType,
 };
+use igloo_interface::{ClimateMode, Component, FanOscillation, FanSpeed};
+use tracing::warn;
 
 // The ESPHome climate entity doesn't really match this ECS model
 // Currently we aren't publishing Humidity or Cur Temp
@@ -32,8 +33,7 @@ impl EntityRegister for api::ListEntitiesClimateResponse {
 
         comps.push(Component::TextSelect);
         comps.push(Component::TextList(
-            self
-                .supported_presets()
+            self.supported_presets()
                 .map(|preset| format!("{preset:#?}"))
                 .chain(self.supported_custom_presets.iter().cloned())
                 .collect(),
@@ -44,10 +44,6 @@ impl EntityRegister for api::ListEntitiesClimateResponse {
 }
 
 impl EntityUpdate for api::ClimateStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         let mut comps = Vec::with_capacity(6);
         comps.push(Component::Real(self.target_temperature as f64));
@@ -60,8 +56,10 @@ impl EntityUpdate for api::ClimateStateResponse {
     }
 }
 
-impl api::ClimateSwingMode {
-    pub fn as_igloo(&self) -> FanOscillation {
+impl AsIgloo for api::ClimateSwingMode {
+    type Igloo = FanOscillation;
+
+    fn as_igloo(&self) -> FanOscillation {
         match self {
             api::ClimateSwingMode::ClimateSwingOff => FanOscillation::Off,
             api::ClimateSwingMode::ClimateSwingBoth => FanOscillation::Both,
@@ -70,8 +68,10 @@ impl api::ClimateSwingMode {
         }
     }
 }
-impl api::ClimateMode {
-    pub fn as_igloo(&self) -> ClimateMode {
+impl AsIgloo for api::ClimateMode {
+    type Igloo = ClimateMode;
+
+    fn as_igloo(&self) -> ClimateMode {
         match self {
             api::ClimateMode::Off => ClimateMode::Off,
             api::ClimateMode::HeatCool => ClimateMode::HeatCool,
@@ -84,8 +84,10 @@ impl api::ClimateMode {
     }
 }
 
-impl api::ClimateFanMode {
-    pub fn as_igloo(&self) -> FanSpeed {
+impl AsIgloo for api::ClimateFanMode {
+    type Igloo = FanSpeed;
+
+    fn as_igloo(&self) -> FanSpeed {
         match self {
             api::ClimateFanMode::ClimateFanOn => FanSpeed::On,
             api::ClimateFanMode::ClimateFanOff => FanSpeed::Off,
@@ -142,10 +144,12 @@ fn fan_oscillation_to_swing(oscillation: &FanOscillation) -> api::ClimateSwingMo
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::ClimateCommandRequest {
         key,
+        device_id,
         ..Default::default()
     };
 
@@ -178,7 +182,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Climate got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected climate component during transaction, skipping"
+                );
             }
         }
     }
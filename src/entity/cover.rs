@@ -1,11 +1,12 @@
-use super::{EntityRegister, add_device_class, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_device_class, add_entity_category, add_icon};
 use crate::{
     api,
+    client::EntityKey,
     device::{Device, DeviceError},
     entity::EntityUpdate,
-    model::MessageType,
 };
 use igloo_interface::{Component, CoverState};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesCoverResponse {
     fn comps(self) -> Vec<Component> {
@@ -18,8 +19,10 @@ impl EntityRegister for api::ListEntitiesCoverResponse {
     }
 }
 
-impl api::CoverOperation {
-    pub fn as_igloo(&self) -> CoverState {
+impl AsIgloo for api::CoverOperation {
+    type Igloo = CoverState;
+
+    fn as_igloo(&self) -> CoverState {
         match self {
             api::CoverOperation::Idle => CoverState::Idle,
             api::CoverOperation::IsOpening => CoverState::Opening,
@@ -29,27 +32,12 @@ impl api::CoverOperation {
 }
 
 impl EntityUpdate for api::CoverStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
-        let mut comps = Vec::with_capacity(3);
-        comps.push(Component::Position(self.position as f64));
-        comps.push(Component::Tilt(self.tilt as f64));
-        comps.push(Component::CoverState(self.current_operation().as_igloo()));
-        comps
-    }
-}
-
-fn cover_state_to_command(state: &CoverState) -> api::LegacyCoverCommand {
-    match state {
-        CoverState::Open => api::LegacyCoverCommand::Open,
-        CoverState::Closed => api::LegacyCoverCommand::Close,
-        CoverState::Opening => api::LegacyCoverCommand::Open,
-        CoverState::Closing => api::LegacyCoverCommand::Close,
-        CoverState::Stopped => api::LegacyCoverCommand::Stop,
-        CoverState::Idle => api::LegacyCoverCommand::Stop,
+        vec![
+            Component::Position(self.position as f64),
+            Component::Tilt(self.tilt as f64),
+            Component::CoverState(self.current_operation().as_igloo()),
+        ]
     }
 }
 
@@ -57,40 +45,35 @@ fn cover_state_to_command(state: &CoverState) -> api::LegacyCoverCommand {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    let mut req = api::CoverCommandRequest {
-        key,
-        ..Default::default()
-    };
+    let mut cmd = device.client.cover(EntityKey::new(device_id, key));
 
     for comp in comps {
         use Component::*;
         match comp {
-            Position(position) => {
-                req.has_position = true;
-                req.position = position as f32;
-            }
-
-            Tilt(tilt) => {
-                req.has_tilt = true;
-                req.tilt = tilt as f32;
-            }
+            Position(position) => cmd = cmd.set_position(position as f32),
+            Tilt(tilt) => cmd = cmd.set_tilt(tilt as f32),
 
             CoverState(state) => {
-                req.has_legacy_command = true;
-                req.legacy_command = cover_state_to_command(&state).into();
+                use igloo_interface::CoverState::*;
+                cmd = match state {
+                    Open | Opening => cmd.open(),
+                    Closed | Closing => cmd.close(),
+                    Stopped | Idle => cmd.stop(),
+                };
             }
 
             comp => {
-                println!(
-                    "Cover got unexpected component '{comp:?}' during transaction. Skipping.."
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected cover component during transaction, skipping"
                 );
             }
         }
     }
 
-    device
-        .send_msg(MessageType::CoverCommandRequest, &req)
-        .await
+    Ok(cmd.send().await?)
 }
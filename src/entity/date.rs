@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::{Component, types::IglooDate};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesDateResponse {
     fn comps(self) -> Vec<Component> {
@@ -17,10 +18,6 @@ impl EntityRegister for api::ListEntitiesDateResponse {
 }
 
 impl EntityUpdate for api::DateStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -37,10 +34,12 @@ impl EntityUpdate for api::DateStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::DateCommandRequest {
         key,
+        device_id,
         year: 0,
         month: 0,
         day: 0,
@@ -56,7 +55,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Date got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected date component during transaction, skipping"
+                );
             }
         }
     }
@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesDateTimeResponse {
     fn comps(self) -> Vec<Component> {
@@ -17,10 +18,6 @@ impl EntityRegister for api::ListEntitiesDateTimeResponse {
 }
 
 impl EntityUpdate for api::DateTimeStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -33,10 +30,12 @@ impl EntityUpdate for api::DateTimeStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::DateTimeCommandRequest {
         key,
+        device_id,
         epoch_seconds: 0,
     };
 
@@ -48,7 +47,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("DateTime got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected datetime component during transaction, skipping"
+                );
             }
         }
     }
@@ -1,6 +1,7 @@
 use super::{EntityRegister, add_device_class, add_entity_category, add_icon};
-use crate::api;
+use crate::{api, entity::EntityUpdate};
 use igloo_interface::Component;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 impl EntityRegister for api::ListEntitiesEventResponse {
     fn comps(self) -> Vec<Component> {
@@ -12,3 +13,17 @@ impl EntityRegister for api::ListEntitiesEventResponse {
         comps
     }
 }
+
+impl EntityUpdate for api::EventResponse {
+    fn comps(&self) -> Vec<Component> {
+        // events carry no time, and the same type can fire twice in a row
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        vec![
+            Component::Text(self.event_type.clone()),
+            Component::Timestamp(now),
+        ]
+    }
+}
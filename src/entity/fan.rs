@@ -1,11 +1,12 @@
-use super::{EntityRegister, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_entity_category, add_icon};
 use crate::{
     api,
+    client::EntityKey,
     device::{Device, DeviceError},
     entity::EntityUpdate,
-    model::MessageType,
 };
 use igloo_interface::{Component, FanDirection, FanOscillation, FanSpeed};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesFanResponse {
     fn comps(self) -> Vec<Component> {
@@ -16,8 +17,10 @@ impl EntityRegister for api::ListEntitiesFanResponse {
     }
 }
 
-impl api::FanDirection {
-    pub fn as_igloo(&self) -> FanDirection {
+impl AsIgloo for api::FanDirection {
+    type Igloo = FanDirection;
+
+    fn as_igloo(&self) -> FanDirection {
         match self {
             api::FanDirection::Forward => FanDirection::Forward,
             api::FanDirection::Reverse => FanDirection::Reverse,
@@ -25,8 +28,10 @@ impl api::FanDirection {
     }
 }
 
-impl api::FanSpeed {
-    pub fn as_igloo(&self) -> FanSpeed {
+impl AsIgloo for api::FanSpeed {
+    type Igloo = FanSpeed;
+
+    fn as_igloo(&self) -> FanSpeed {
         match self {
             api::FanSpeed::Low => FanSpeed::Low,
             api::FanSpeed::Medium => FanSpeed::Medium,
@@ -36,10 +41,6 @@ impl api::FanSpeed {
 }
 
 impl EntityUpdate for api::FanStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         vec![
             Component::FanSpeed(self.speed().as_igloo()),
@@ -65,46 +66,32 @@ fn fan_direction_to_api(direction: &FanDirection) -> api::FanDirection {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    let mut req = api::FanCommandRequest {
-        key,
-        ..Default::default()
-    };
+    let mut cmd = device.client.fan(EntityKey::new(device_id, key));
 
     for comp in comps {
         use Component::*;
         match comp {
-            Switch(state) => {
-                req.has_state = true;
-                req.state = state;
-            }
-
-            Integer(speed_level) => {
-                req.has_speed_level = true;
-                req.speed_level = speed_level as i32;
-            }
-
+            Switch(state) => cmd = cmd.state(state),
+            // mapped to low/medium/high on older devices
+            Integer(speed_level) => cmd = cmd.speed_level(speed_level as i32),
             FanOscillation(oscillation) => {
-                req.has_oscillating = true;
-                req.oscillating = !matches!(oscillation, igloo_interface::FanOscillation::Off);
-            }
-
-            FanDirection(direction) => {
-                req.has_direction = true;
-                req.direction = fan_direction_to_api(&direction).into();
-            }
-
-            Text(preset) => {
-                req.has_preset_mode = true;
-                req.preset_mode = preset;
+                cmd = cmd.oscillating(!matches!(oscillation, igloo_interface::FanOscillation::Off));
             }
+            FanDirection(direction) => cmd = cmd.direction(fan_direction_to_api(&direction)),
+            Text(preset) => cmd = cmd.preset_mode(preset),
 
             comp => {
-                println!("Fan got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected fan component during transaction, skipping"
+                );
             }
         }
     }
 
-    device.send_msg(MessageType::FanCommandRequest, &req).await
+    Ok(cmd.send().await?)
 }
@@ -0,0 +1,140 @@
+use crate::{
+    api,
+    client::EntityKey,
+    model::{EntityMessage, EntityType},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Identity fields shared by every `ListEntities*Response`
+pub trait EntityIdentity {
+    fn key(&self) -> u32;
+    fn object_id(&self) -> &str;
+    fn unique_id(&self) -> &str;
+    fn name(&self) -> &str;
+    /// Firmware author wants this hidden unless the user enables it
+    fn disabled_by_default(&self) -> bool;
+}
+
+macro_rules! impl_entity_identity {
+    ($($(#[$cfg:meta])* $msg:ty),* $(,)?) => {
+        $(
+            $(#[$cfg])*
+            impl EntityIdentity for $msg {
+                fn key(&self) -> u32 {
+                    self.key
+                }
+
+                fn object_id(&self) -> &str {
+                    &self.object_id
+                }
+
+                fn unique_id(&self) -> &str {
+                    &self.unique_id
+                }
+
+                fn name(&self) -> &str {
+                    &self.name
+                }
+
+                fn disabled_by_default(&self) -> bool {
+                    self.disabled_by_default
+                }
+            }
+        )*
+    };
+}
+
+impl_entity_identity!(
+    api::ListEntitiesBinarySensorResponse,
+    api::ListEntitiesCoverResponse,
+    api::ListEntitiesFanResponse,
+    api::ListEntitiesLightResponse,
+    api::ListEntitiesSensorResponse,
+    api::ListEntitiesSwitchResponse,
+    api::ListEntitiesTextSensorResponse,
+    #[cfg(feature = "camera")]
+    api::ListEntitiesCameraResponse,
+    api::ListEntitiesClimateResponse,
+    api::ListEntitiesNumberResponse,
+    api::ListEntitiesSelectResponse,
+    api::ListEntitiesSirenResponse,
+    api::ListEntitiesLockResponse,
+    api::ListEntitiesButtonResponse,
+    #[cfg(feature = "media")]
+    api::ListEntitiesMediaPlayerResponse,
+    api::ListEntitiesAlarmControlPanelResponse,
+    api::ListEntitiesTextResponse,
+    api::ListEntitiesDateResponse,
+    api::ListEntitiesTimeResponse,
+    api::ListEntitiesEventResponse,
+    api::ListEntitiesValveResponse,
+    api::ListEntitiesDateTimeResponse,
+    api::ListEntitiesUpdateResponse,
+);
+
+/// Hands out Igloo entity IDs for one device, never the same one twice
+#[derive(Debug, Default)]
+pub struct EntityIds {
+    used: HashSet<String>,
+    /// maps ESPHome entity -> assigned ID
+    by_key: HashMap<EntityKey, String>,
+}
+
+impl EntityIds {
+    /// `<entity_type>.<object_id>`, falling back to the unique ID then the key
+    /// when the firmware leaves `object_id` empty. Suffixed with `_2`, `_3`, ..
+    /// if already taken. The same key always gets the same ID.
+    pub fn assign(
+        &mut self,
+        entity_type: &EntityType,
+        msg: &(impl EntityIdentity + EntityMessage),
+    ) -> String {
+        if let Some(id) = self.by_key.get(&msg.entity_key()) {
+            return id.clone();
+        }
+
+        let base = if !msg.object_id().is_empty() {
+            format!("{entity_type}.{}", sanitize(msg.object_id()))
+        } else if !msg.unique_id().is_empty() {
+            format!("{entity_type}.{}", sanitize(msg.unique_id()))
+        } else {
+            format!("{entity_type}.{:08x}", msg.key())
+        };
+
+        let mut id = base.clone();
+        let mut n = 2;
+        while self.used.contains(&id) {
+            id = format!("{base}_{n}");
+            n += 1;
+        }
+
+        self.used.insert(id.clone());
+        self.by_key.insert(msg.entity_key(), id.clone());
+        id
+    }
+
+    /// Reserve an ID made by the extension itself
+    pub fn reserve(&mut self, id: &str) {
+        self.used.insert(id.to_string());
+    }
+}
+
+/// Name to show in Igloo. Modern ESPHome leaves `name` empty for
+/// the device's main entity, which should just take the device's name.
+pub fn display_name(msg: &impl EntityIdentity, device_name: &str) -> String {
+    if msg.name().is_empty() {
+        device_name.to_string()
+    } else {
+        msg.name().to_string()
+    }
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '-' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
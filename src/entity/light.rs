@@ -1,11 +1,13 @@
 use super::{EntityRegister, add_entity_category, add_icon};
 use crate::{
     api,
+    client::{ApiVersion, EntityKey, command::COLOR_MODE_API_VERSION},
     device::{Device, DeviceError},
     entity::EntityUpdate,
-    model::MessageType,
 };
 use igloo_interface::{ColorMode, Component, types::IglooColor};
+use std::time::Duration;
+use tracing::warn;
 
 impl EntityRegister for crate::api::ListEntitiesLightResponse {
     fn comps(self) -> Vec<Component> {
@@ -15,6 +17,66 @@ impl EntityRegister for crate::api::ListEntitiesLightResponse {
         comps.push(Component::Light);
         comps
     }
+
+    fn comps_on(self, api_version: ApiVersion) -> Vec<Component> {
+        let caps = capabilities(&self, api_version);
+        let warmest = self.max_mireds;
+        let mut comps = self.comps();
+
+        comps.push(Component::Switch(false));
+        if caps & BRIGHTNESS != 0 {
+            comps.push(Component::Dimmer(0.));
+        }
+        if caps & RGB != 0 {
+            comps.push(Component::Color(IglooColor {
+                r: 0.,
+                g: 0.,
+                b: 0.,
+            }));
+        }
+        let temperature = caps & (COLOR_TEMPERATURE | COLD_WARM_WHITE) != 0;
+        if temperature && warmest > 0. {
+            comps.push(Component::ColorTemperature(
+                mireds_to_kelvin(warmest as f64) as i64,
+            ));
+        }
+        // only color modes can switch between the two
+        if api_version >= COLOR_MODE_API_VERSION && caps & RGB != 0 && temperature {
+            comps.push(Component::ColorMode(ColorMode::RGB));
+        }
+
+        comps
+    }
+}
+
+// ESPHome `ColorCapability` bits
+const ON_OFF: i32 = 1 << 0;
+const BRIGHTNESS: i32 = 1 << 1;
+const WHITE: i32 = 1 << 2;
+const COLOR_TEMPERATURE: i32 = 1 << 3;
+const COLD_WARM_WHITE: i32 = 1 << 4;
+const RGB: i32 = 1 << 5;
+
+/// Every capability of the light. Devices before color modes only fill
+/// the `legacy_supports_*` flags, newer ones only `supported_color_modes`.
+#[allow(deprecated)]
+fn capabilities(light: &api::ListEntitiesLightResponse, api_version: ApiVersion) -> i32 {
+    if api_version >= COLOR_MODE_API_VERSION {
+        return light
+            .supported_color_modes
+            .iter()
+            .fold(0, |caps, mode| caps | mode);
+    }
+
+    [
+        (light.legacy_supports_brightness, BRIGHTNESS),
+        (light.legacy_supports_rgb, RGB),
+        (light.legacy_supports_white_value, WHITE),
+        (light.legacy_supports_color_temperature, COLOR_TEMPERATURE),
+    ]
+    .into_iter()
+    .filter(|(supported, _)| *supported)
+    .fold(ON_OFF, |caps, (_, cap)| caps | cap)
 }
 
 pub fn kelvin_to_mireds(kelvin: i64) -> f64 {
@@ -26,10 +88,6 @@ pub fn mireds_to_kelvin(mireds: f64) -> u16 {
 }
 
 impl EntityUpdate for api::LightStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         let mut comps = Vec::with_capacity(5);
 
@@ -45,18 +103,11 @@ impl EntityUpdate for api::LightStateResponse {
             mireds_to_kelvin(self.color_temperature as f64) as i64,
         ));
 
-        // ON_OFF = 1 << 0;
-        // BRIGHTNESS = 1 << 1;
-        // WHITE = 1 << 2;
-        // COLOR_TEMPERATURE = 1 << 3;
-        // COLD_WARM_WHITE = 1 << 4;
-        // RGB = 1 << 5;
-
         // TODO FIXME is this right? Lowk i don't get the other ones
 
-        if self.color_mode & (1 << 5) != 0 {
+        if self.color_mode & RGB != 0 {
             comps.push(Component::ColorMode(ColorMode::RGB));
-        } else if self.color_mode & (1 << 3) != 0 {
+        } else if self.color_mode & COLOR_TEMPERATURE != 0 {
             comps.push(Component::ColorMode(ColorMode::Temperature));
         }
 
@@ -68,63 +119,51 @@ impl EntityUpdate for api::LightStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    let mut req = api::LightCommandRequest {
-        key,
-        has_transition_length: true,
-        transition_length: 0,
-        ..Default::default()
-    };
+    let api_version = device.api_version();
+    let mut cmd = device
+        .client
+        .light(EntityKey::new(device_id, key))
+        .transition(Duration::ZERO);
 
     for comp in comps {
         use Component::*;
         match comp {
-            Color(color) => {
-                req.has_rgb = true;
-                req.red = color.r as f32;
-                req.green = color.g as f32;
-                req.blue = color.b as f32;
-            }
-
-            Dimmer(val) => {
-                // req.has_color_brightness = true;
-                // req.color_brightness = val;
-                req.has_brightness = true;
-                req.brightness = val as f32;
-
-                req.has_state = true;
-                req.state = val > 0.;
-            }
-
-            Switch(state) => {
-                req.has_state = true;
-                req.state = state;
+            Color(color) => cmd = cmd.rgb(color.r as f32, color.g as f32, color.b as f32),
+            Dimmer(val) => cmd = cmd.brightness(val as f32).state(val > 0.),
+            Switch(state) => cmd = cmd.state(state),
+            ColorTemperature(temp_kelvin) => {
+                cmd = cmd.color_temperature(kelvin_to_mireds(temp_kelvin) as f32);
             }
 
-            ColorTemperature(temp_kelvin) => {
-                req.has_color_temperature = true;
-                req.color_temperature = kelvin_to_mireds(temp_kelvin) as f32;
+            ColorMode(mode) if api_version < COLOR_MODE_API_VERSION => {
+                warn!(
+                    key,
+                    ?mode,
+                    %api_version,
+                    "light can't set color mode on this API version, skipping"
+                );
             }
 
             ColorMode(mode) => {
                 use igloo_interface::ColorMode::*;
-                req.has_color_mode = true;
-                req.color_mode = match mode {
+                cmd = cmd.color_mode(match mode {
                     RGB => 35,
                     Temperature => 11,
-                };
+                });
             }
 
             comp => {
-                println!(
-                    "Light got unexpected component '{comp:?}' during transaction. Skipping.."
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected light component during transaction, skipping"
                 );
             }
         }
     }
 
-    device
-        .send_msg(MessageType::LightCommandRequest, &req)
-        .await
+    Ok(cmd.send().await?)
 }
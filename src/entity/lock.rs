@@ -1,4 +1,4 @@
-use super::{EntityRegister, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_entity_category, add_icon};
 use crate::{
     api,
     device::{Device, DeviceError},
@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::{Component, LockState};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesLockResponse {
     fn comps(self) -> Vec<Component> {
@@ -17,7 +18,9 @@ impl EntityRegister for api::ListEntitiesLockResponse {
     }
 }
 
-impl api::LockState {
+impl AsIgloo for api::LockState {
+    type Igloo = LockState;
+
     fn as_igloo(&self) -> LockState {
         match self {
             api::LockState::None => LockState::Unknown,
@@ -31,10 +34,6 @@ impl api::LockState {
 }
 
 impl EntityUpdate for api::LockStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         vec![Component::LockState(self.state().as_igloo())]
     }
@@ -54,10 +53,12 @@ fn lock_state_to_command(state: &LockState) -> api::LockCommand {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::LockCommandRequest {
         key,
+        device_id,
         command: api::LockCommand::LockLock.into(),
         has_code: false,
         code: String::new(),
@@ -76,7 +77,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Lock got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected lock component during transaction, skipping"
+                );
             }
         }
     }
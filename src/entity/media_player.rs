@@ -1,4 +1,4 @@
-use super::{EntityRegister, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_entity_category, add_icon};
 use crate::{
     api,
     device::{Device, DeviceError},
@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::{Component, MediaState};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesMediaPlayerResponse {
     fn comps(self) -> Vec<Component> {
@@ -16,7 +17,9 @@ impl EntityRegister for api::ListEntitiesMediaPlayerResponse {
     }
 }
 
-impl api::MediaPlayerState {
+impl AsIgloo for api::MediaPlayerState {
+    type Igloo = MediaState;
+
     fn as_igloo(&self) -> MediaState {
         match self {
             api::MediaPlayerState::None => MediaState::Unknown,
@@ -28,16 +31,12 @@ impl api::MediaPlayerState {
 }
 
 impl EntityUpdate for api::MediaPlayerStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
-        let mut comps = Vec::with_capacity(3);
-        comps.push(Component::Volume(self.volume as f64));
-        comps.push(Component::Muted(self.muted));
-        comps.push(Component::MediaState(self.state().as_igloo()));
-        comps
+        vec![
+            Component::Volume(self.volume as f64),
+            Component::Muted(self.muted),
+            Component::MediaState(self.state().as_igloo()),
+        ]
     }
 }
 
@@ -53,10 +52,12 @@ fn media_state_to_command(state: &MediaState) -> api::MediaPlayerCommand {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::MediaPlayerCommandRequest {
         key,
+        device_id,
         ..Default::default()
     };
 
@@ -84,8 +85,10 @@ pub async fn process(
             }
 
             comp => {
-                println!(
-                    "MediaPlayer got unexpected component '{comp:?}' during transaction. Skipping.."
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected media player component during transaction, skipping"
                 );
             }
         }
@@ -1,9 +1,10 @@
-use crate::api;
+use crate::{api, client::ApiVersion, model::EntityMessage};
 use igloo_interface::{Component, SensorStateClass, Unit};
 
 pub mod alarm_control_panel;
 pub mod binary_sensor;
 pub mod button;
+#[cfg(feature = "camera")]
 pub mod camera;
 pub mod climate;
 pub mod cover;
@@ -11,30 +12,59 @@ pub mod date;
 pub mod date_time;
 pub mod event;
 pub mod fan;
+pub mod identity;
 pub mod light;
 pub mod lock;
+#[cfg(feature = "media")]
 pub mod media_player;
 pub mod number;
 pub mod select;
 pub mod sensor;
 pub mod siren;
+pub mod status;
 pub mod switch;
 pub mod text;
 pub mod text_sensor;
 pub mod time;
 pub mod update;
 pub mod valve;
+#[cfg(feature = "voice")]
+pub mod voice_assistant;
 
-pub trait EntityUpdate {
-    fn key(&self) -> u32;
+pub trait EntityUpdate: EntityMessage {
     fn should_skip(&self) -> bool {
         false
     }
     fn comps(&self) -> Vec<Component>;
 }
 
-pub trait EntityRegister {
+pub trait EntityRegister: identity::EntityIdentity + EntityMessage + Sized {
     fn comps(self) -> Vec<Component>;
+
+    /// [`Self::comps`] for a device on `api_version`, for entities whose
+    /// capabilities live in different fields depending on the version
+    fn comps_on(self, _api_version: ApiVersion) -> Vec<Component> {
+        self.comps()
+    }
+}
+
+/// An `api` enum with an Igloo counterpart
+pub trait AsIgloo {
+    type Igloo;
+    fn as_igloo(&self) -> Self::Igloo;
+}
+
+/// Entities made by the extension, not listed by the device
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SyntheticEntity {
+    /// Connection status and device info
+    Status,
+    /// Active wake words of a voice assistant satellite
+    #[cfg(feature = "voice")]
+    WakeWords,
+    /// One available wake word, by its ID
+    #[cfg(feature = "voice")]
+    WakeWord(String),
 }
 
 pub fn add_entity_category(comps: &mut Vec<Component>, category: api::EntityCategory) {
@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesNumberResponse {
     fn comps(self) -> Vec<Component> {
@@ -21,10 +22,6 @@ impl EntityRegister for api::ListEntitiesNumberResponse {
 }
 
 impl EntityUpdate for api::NumberStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -48,9 +45,14 @@ impl EntityUpdate for api::NumberStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    let mut req = api::NumberCommandRequest { key, state: 0.0 };
+    let mut req = api::NumberCommandRequest {
+        key,
+        device_id,
+        state: 0.0,
+    };
 
     for comp in comps {
         use Component::*;
@@ -60,7 +62,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Number got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected number component during transaction, skipping"
+                );
             }
         }
     }
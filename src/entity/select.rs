@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesSelectResponse {
     fn comps(self) -> Vec<Component> {
@@ -19,10 +20,6 @@ impl EntityRegister for api::ListEntitiesSelectResponse {
 }
 
 impl EntityUpdate for api::SelectStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -36,10 +33,12 @@ impl EntityUpdate for api::SelectStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::SelectCommandRequest {
         key,
+        device_id,
         state: String::new(),
     };
 
@@ -51,7 +50,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Select got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected select component during transaction, skipping"
+                );
             }
         }
     }
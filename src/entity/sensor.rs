@@ -20,10 +20,6 @@ impl EntityRegister for api::ListEntitiesSensorResponse {
 }
 
 impl EntityUpdate for api::SensorStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesSirenResponse {
     fn comps(self) -> Vec<Component> {
@@ -20,10 +21,6 @@ impl EntityRegister for api::ListEntitiesSirenResponse {
 }
 
 impl EntityUpdate for api::SirenStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         vec![Component::Boolean(self.state)]
     }
@@ -32,10 +29,12 @@ impl EntityUpdate for api::SirenStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::SirenCommandRequest {
         key,
+        device_id,
         ..Default::default()
     };
 
@@ -63,7 +62,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Siren got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected siren component during transaction, skipping"
+                );
             }
         }
     }
@@ -0,0 +1,56 @@
+use crate::{api, client::ApiVersion, custom};
+use igloo_interface::Component;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Connection health of a device, published as its status entity
+#[derive(Clone, Debug, Default)]
+pub struct DeviceStatus {
+    pub connected: bool,
+    /// last time any message was received
+    pub last_seen: Option<SystemTime>,
+    /// number of successful reconnects since the extension started
+    pub reconnects: u64,
+    /// round trip of the last keepalive ping
+    pub ping_latency: Option<Duration>,
+}
+
+pub fn comps(
+    status: &DeviceStatus,
+    info: Option<&api::DeviceInfoResponse>,
+    api_version: ApiVersion,
+    server_info: &str,
+) -> Vec<Component> {
+    let mut comps = Vec::with_capacity(6);
+    comps.push(Component::Diagnostic);
+    comps.push(Component::Boolean(status.connected));
+
+    // FIXME same as the update entity, this should probably be
+    // split into more entities once Igloo has a nicer way to group them
+    if let Some(info) = info {
+        comps.push(Component::Text(format!(
+            "api_version:{api_version},server_info:{server_info},esphome_version:{},compilation_time:{},model:{},mac_address:{},project_name:{},project_version:{},suggested_area:{}",
+            info.esphome_version,
+            info.compilation_time,
+            info.model,
+            info.mac_address,
+            info.project_name,
+            info.project_version,
+            custom::area(info)
+        )));
+    }
+
+    if let Some(last_seen) = status.last_seen
+        && let Ok(since_epoch) = last_seen.duration_since(UNIX_EPOCH)
+    {
+        comps.push(Component::Timestamp(since_epoch.as_secs() as i64));
+    }
+
+    comps.push(Component::Integer(status.reconnects as i64));
+
+    if let Some(latency) = status.ping_latency {
+        // milliseconds
+        comps.push(Component::Real(latency.as_secs_f64() * 1000.));
+    }
+
+    comps
+}
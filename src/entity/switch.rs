@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesSwitchResponse {
     fn comps(self) -> Vec<Component> {
@@ -18,10 +19,6 @@ impl EntityRegister for api::ListEntitiesSwitchResponse {
 }
 
 impl EntityUpdate for api::SwitchStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
         vec![Component::Switch(self.state)]
     }
@@ -31,9 +28,14 @@ impl EntityUpdate for api::SwitchStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    let mut req = api::SwitchCommandRequest { key, state: false };
+    let mut req = api::SwitchCommandRequest {
+        key,
+        device_id,
+        state: false,
+    };
 
     for comp in comps {
         use Component::*;
@@ -43,8 +45,10 @@ pub async fn process(
             }
 
             comp => {
-                println!(
-                    "Switch got unexpected component '{comp:?}' during transaction. Skipping.."
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected switch component during transaction, skipping"
                 );
             }
         }
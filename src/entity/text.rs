@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::Component;
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesTextResponse {
     fn comps(self) -> Vec<Component> {
@@ -30,10 +31,6 @@ impl EntityRegister for api::ListEntitiesTextResponse {
 // }
 
 impl EntityUpdate for api::TextStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -47,10 +44,12 @@ impl EntityUpdate for api::TextStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::TextCommandRequest {
         key,
+        device_id,
         state: String::new(),
     };
 
@@ -62,7 +61,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Text got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected text component during transaction, skipping"
+                );
             }
         }
     }
@@ -14,10 +14,6 @@ impl EntityRegister for api::ListEntitiesTextSensorResponse {
 }
 
 impl EntityUpdate for api::TextSensorStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
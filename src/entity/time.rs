@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::{Component, types::IglooTime};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesTimeResponse {
     fn comps(self) -> Vec<Component> {
@@ -17,10 +18,6 @@ impl EntityRegister for api::ListEntitiesTimeResponse {
 }
 
 impl EntityUpdate for api::TimeStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -37,10 +34,12 @@ impl EntityUpdate for api::TimeStateResponse {
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::TimeCommandRequest {
         key,
+        device_id,
         hour: 0,
         minute: 0,
         second: 0,
@@ -56,7 +55,11 @@ pub async fn process(
             }
 
             comp => {
-                println!("Time got unexpected component '{comp:?}' during transaction. Skipping..");
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected time component during transaction, skipping"
+                );
             }
         }
     }
@@ -1,4 +1,5 @@
 use igloo_interface::Component;
+use tracing::warn;
 
 use super::{EntityRegister, add_device_class, add_entity_category, add_icon};
 use crate::{
@@ -18,10 +19,6 @@ impl EntityRegister for api::ListEntitiesUpdateResponse {
 }
 
 impl EntityUpdate for api::UpdateStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn should_skip(&self) -> bool {
         self.missing_state
     }
@@ -55,9 +52,10 @@ impl EntityUpdate for api::UpdateStateResponse {
 pub async fn process(
     _device: &mut Device,
     _key: u32,
+    _device_id: u32,
     _comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
-    eprintln!("ESPHOME UPDATE ENTITY IS NOT IMPLEMENTED");
+    warn!("ESPHome update entity is not implemented");
     Ok(())
 
     // TODO how should we be handling this?
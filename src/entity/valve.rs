@@ -1,4 +1,4 @@
-use super::{EntityRegister, add_device_class, add_entity_category, add_icon};
+use super::{AsIgloo, EntityRegister, add_device_class, add_entity_category, add_icon};
 use crate::{
     api,
     device::{Device, DeviceError},
@@ -6,6 +6,7 @@ use crate::{
     model::MessageType,
 };
 use igloo_interface::{Component, ValveState};
+use tracing::warn;
 
 impl EntityRegister for api::ListEntitiesValveResponse {
     fn comps(self) -> Vec<Component> {
@@ -18,7 +19,9 @@ impl EntityRegister for api::ListEntitiesValveResponse {
     }
 }
 
-impl api::ValveOperation {
+impl AsIgloo for api::ValveOperation {
+    type Igloo = ValveState;
+
     fn as_igloo(&self) -> ValveState {
         match self {
             api::ValveOperation::Idle => ValveState::Idle,
@@ -29,25 +32,23 @@ impl api::ValveOperation {
 }
 
 impl EntityUpdate for api::ValveStateResponse {
-    fn key(&self) -> u32 {
-        self.key
-    }
-
     fn comps(&self) -> Vec<Component> {
-        let mut comps = Vec::with_capacity(2);
-        comps.push(Component::Position(self.position as f64));
-        comps.push(Component::ValveState(self.current_operation().as_igloo()));
-        comps
+        vec![
+            Component::Position(self.position as f64),
+            Component::ValveState(self.current_operation().as_igloo()),
+        ]
     }
 }
 
 pub async fn process(
     device: &mut Device,
     key: u32,
+    device_id: u32,
     comps: Vec<Component>,
 ) -> Result<(), DeviceError> {
     let mut req = api::ValveCommandRequest {
         key,
+        device_id,
         ..Default::default()
     };
 
@@ -68,8 +69,10 @@ pub async fn process(
             }
 
             comp => {
-                println!(
-                    "Valve got unexpected component '{comp:?}' during transaction. Skipping.."
+                warn!(
+                    key,
+                    ?comp,
+                    "unexpected valve component during transaction, skipping"
                 );
             }
         }
@@ -0,0 +1,114 @@
+use super::SyntheticEntity;
+use crate::{
+    api,
+    device::{Device, DeviceError},
+    model::MessageType,
+};
+use igloo_interface::Component;
+use tracing::warn;
+
+/// `DeviceInfoResponse.voice_assistant_feature_flags` bit for voice assistant support
+pub const FEATURE_VOICE_ASSISTANT: u32 = 1 << 0;
+
+/// Whether the device is a voice assistant satellite (and therefore
+/// answers `VoiceAssistantConfigurationRequest`)
+pub fn is_satellite(info: &api::DeviceInfoResponse) -> bool {
+    info.voice_assistant_feature_flags & FEATURE_VOICE_ASSISTANT != 0
+        || info.legacy_voice_assistant_version != 0
+}
+
+/// The wake word multi-select. `TextList` is the active selection,
+/// each option is published separately with [`wake_word_comps`]
+pub fn wake_words_comps(config: &api::VoiceAssistantConfigurationResponse) -> Vec<Component> {
+    vec![
+        Component::TextList(config.active_wake_words.clone()),
+        Component::Integer(config.max_active_wake_words as i64),
+    ]
+}
+
+/// One option of the wake word multi-select
+pub fn wake_word_comps(
+    config: &api::VoiceAssistantConfigurationResponse,
+    wake_word: &api::VoiceAssistantWakeWord,
+) -> Vec<Component> {
+    vec![
+        Component::Text(wake_word.wake_word.clone()),
+        Component::TextList(wake_word.trained_languages.clone()),
+        Component::Switch(config.active_wake_words.contains(&wake_word.id)),
+    ]
+}
+
+pub async fn process(
+    device: &mut Device,
+    entity: SyntheticEntity,
+    comps: Vec<Component>,
+) -> Result<(), DeviceError> {
+    let Some(config) = &device.voice_config else {
+        warn!("Igloo wrote to wake words before configuration was received, skipping");
+        return Ok(());
+    };
+
+    let mut active = config.active_wake_words.clone();
+
+    for comp in comps {
+        use Component::*;
+        match (&entity, comp) {
+            (SyntheticEntity::WakeWords, TextList(ids)) => {
+                active = ids;
+            }
+
+            (SyntheticEntity::WakeWord(id), Switch(true)) => {
+                if !active.contains(id) {
+                    active.push(id.clone());
+                }
+            }
+
+            (SyntheticEntity::WakeWord(id), Switch(false)) => {
+                active.retain(|a| a != id);
+            }
+
+            (_, comp) => {
+                warn!(
+                    ?entity,
+                    ?comp,
+                    "unexpected wake word component during transaction, skipping"
+                );
+            }
+        }
+    }
+
+    if let Some(unknown) = active
+        .iter()
+        .find(|id| !config.available_wake_words.iter().any(|w| &w.id == *id))
+    {
+        warn!(wake_word = unknown, "unknown wake word, skipping");
+        return Ok(());
+    }
+
+    let max = config.max_active_wake_words as usize;
+    if max > 0 && active.len() > max {
+        warn!(
+            max,
+            active = active.len(),
+            "too many active wake words, skipping"
+        );
+        return Ok(());
+    }
+
+    device
+        .send_msg(
+            MessageType::VoiceAssistantSetConfiguration,
+            &api::VoiceAssistantSetConfiguration {
+                active_wake_words: active,
+            },
+        )
+        .await?;
+
+    // the device doesn't confirm, so ask for the new configuration
+    device
+        .send_msg(
+            MessageType::VoiceAssistantConfigurationRequest,
+            &api::VoiceAssistantConfigurationRequest {},
+        )
+        .await
+}
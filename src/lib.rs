@@ -0,0 +1,11 @@
+//! Igloo extension for ESPHome devices, built on [`esphome_client`].
+
+pub use esphome_client::{api, client, connection, model};
+
+pub mod commands;
+pub mod config;
+pub mod custom;
+pub mod device;
+pub mod entity;
+pub mod logging;
+pub mod secret;
@@ -0,0 +1,83 @@
+use crate::config::ConfigManager;
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    EnvFilter, Registry, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+/// Overrides every configured level. Takes full `EnvFilter` directives,
+/// ie. `info,igloo_esphome[device{id=3}]=trace`.
+pub const LOG_ENV: &str = "IGLOO_ESPHOME_LOG";
+
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::INFO;
+
+/// Swaps the filter once the config is loaded
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// [`LOG_ENV`] was set, so the config is ignored
+    from_env: bool,
+}
+
+/// Log to stderr at [`DEFAULT_LEVEL`], or as set by [`LOG_ENV`]
+pub fn init() -> LogFilter {
+    let env = std::env::var(LOG_ENV).ok();
+    let (filter, env_error) = match env.as_deref().map(EnvFilter::try_new) {
+        Some(Ok(filter)) => (filter, None),
+        Some(Err(e)) => (EnvFilter::new(DEFAULT_LEVEL.to_string()), Some(e)),
+        None => (EnvFilter::new(DEFAULT_LEVEL.to_string()), None),
+    };
+    let from_env = env.is_some() && env_error.is_none();
+
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .init();
+
+    if let Some(e) = env_error {
+        tracing::warn!("{LOG_ENV} is invalid, ignoring it: {e}");
+    }
+
+    LogFilter { handle, from_env }
+}
+
+impl LogFilter {
+    /// Apply the configured global level and per device levels
+    pub fn apply<'a>(
+        &self,
+        level: Option<&str>,
+        devices: impl IntoIterator<Item = (u64, &'a str)>,
+    ) -> Result<(), String> {
+        if self.from_env {
+            return Ok(());
+        }
+
+        let level = match level {
+            Some(level) => parse_level(level)?,
+            None => DEFAULT_LEVEL,
+        };
+        let mut directives = level.to_string();
+        for (did, level) in devices {
+            // everything inside the device's span, including its connection
+            let level = parse_level(level)?;
+            directives.push_str(&format!(",[device{{id={did}}}]={level}"));
+        }
+
+        let filter = EnvFilter::try_new(&directives).map_err(|e| e.to_string())?;
+        self.handle.reload(filter).map_err(|e| e.to_string())
+    }
+
+    /// [`Self::apply`] the levels in `cm`. Again whenever devices come or go,
+    /// since per device levels are keyed by their ID.
+    pub fn apply_config(&self, cm: &ConfigManager) -> Result<(), String> {
+        let devices = cm
+            .devices()
+            .iter()
+            .filter_map(|(did, params)| Some((*did, params.log_level.as_deref()?)));
+        self.apply(cm.log_level(), devices)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| format!("invalid log level `{level}`"))
+}
@@ -1,48 +1,22 @@
-use crate::device::{ConnectionParams, Device};
 use futures_util::{SinkExt, StreamExt};
-use igloo_interface::ipc::{self, AsyncWriteExtensionToIgloo, IglooToExtension};
-use rustc_hash::{FxBuildHasher, FxHashMap};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, io::SeekFrom, path::PathBuf, sync::Arc};
-use tokio::{
-    fs::{self, File},
-    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
-    sync::Mutex,
+use igloo_esphome::{
+    commands,
+    config::ConfigManager,
+    connection::{error::ConnectionError, noise},
+    custom::{self, CommandResult, ErrorKind, ErrorReport},
+    device::{Device, DeviceControl, PendingSubDevices},
+    logging,
+    secret::Secret,
 };
-
-pub mod connection;
-pub mod device;
-pub mod entity;
-pub mod api {
-    include!(concat!(env!("OUT_DIR"), "/_.rs"));
-}
-pub mod model {
-    include!(concat!(env!("OUT_DIR"), "/model.rs"));
-}
-
-pub const CONFIG_FILE: &str = "config.toml";
-
-/// Eventually this will be described in the Igloo.toml file
-pub const ADD_DEVICE: u16 = 32;
-
-#[derive(Debug)]
-pub struct ConfigManager {
-    file: File,
-    config: Config,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct Config {
-    /// maps Persisnt Igloo Device ID -> Connection Params
-    #[serde(rename = "device")]
-    devices: FxHashMap<u64, ConnectionParams>,
-}
-
-pub type CommandAndPayload = (u16, Vec<u8>);
+use igloo_interface::ipc::{self, ExtensionToIgloo, IglooToExtension};
+use rustc_hash::{FxBuildHasher, FxHashMap};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+use tracing::{Instrument, error, info, warn};
 
 #[tokio::main]
 async fn main() {
-    let mut cm = ConfigManager::load().await.unwrap();
+    let log_filter = Arc::new(logging::init());
 
     let (mut writer, mut reader) = ipc::connect()
         .await
@@ -50,44 +24,59 @@ async fn main() {
 
     // writer task
     let (write_tx, write_rx) = kanal::bounded_async(100);
-    tokio::spawn(async move {
+    let write_task = tokio::spawn(async move {
         loop {
             let msg = match write_rx.recv().await {
                 Ok(msg) => msg,
                 Err(e) => {
-                    eprintln!("Error reading from write_rx: {e}");
+                    error!("error reading from write_rx: {e}");
                     break;
                 }
             };
 
             if let Err(e) = writer.send(msg).await {
-                eprintln!("Error writing message to Igloo: {e}");
+                error!("error writing message to Igloo: {e}");
             }
         }
 
-        println!("Write task shutdown");
+        info!("write task shutdown");
     });
 
+    let cm = match ConfigManager::load().await {
+        Ok(cm) => Arc::new(Mutex::new(cm)),
+        Err(e) => {
+            // don't start with an empty config, the next save would overwrite it
+            let e = ErrorReport::new(ErrorKind::Internal, format!("failed to load config: {e}"));
+            custom::report(&write_tx, e).await;
+            // let the report reach Igloo
+            drop(write_tx);
+            let _ = write_task.await;
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = log_filter.apply_config(&*cm.lock().await) {
+        warn!("keeping the default log level: {e}");
+    }
+
     // Device ID -> Device Channel
     let mut device_txs = HashMap::with_capacity_and_hasher(20, FxBuildHasher);
+    // Sub-device ID -> ID of the node it's part of, whose channel its writes go to
+    let mut sub_devices: FxHashMap<u64, u64> = HashMap::default();
+    let pending_sub_devices = PendingSubDevices::default();
 
     // connect to devices in config
-    for (device_id, params) in cm.config.devices.clone() {
+    let devices = cm.lock().await.devices().clone();
+    for (device_id, params) in devices {
         let (device_tx, deivce_rx) = kanal::bounded_async(50);
         device_txs.insert(device_id, device_tx);
+        for sub_did in params.sub_devices.values() {
+            sub_devices.insert(*sub_did, device_id);
+        }
         let mut device = Device::new(device_id, params);
-        let write_tx_1 = write_tx.clone();
-        tokio::spawn(async move {
-            let did = device.id;
-            if let Err(e) = device.connect().await {
-                eprintln!("Error connecting to device ID={did}: {e}");
-                return;
-            }
-            println!("Device ID={did} connected.");
-            if let Err(e) = device.run(write_tx_1, deivce_rx).await {
-                eprintln!("Error running device ID={did}: {e}");
-            }
-        });
+        device.pending_sub_devices = Some(pending_sub_devices.clone());
+        // connects (and reconnects) on its own
+        tokio::spawn(run_device(device, write_tx.clone(), deivce_rx));
     }
 
     let pending_creation: Arc<Mutex<FxHashMap<String, Device>>> = Arc::new(Mutex::new(
@@ -98,7 +87,9 @@ async fn main() {
         let msg = match res {
             Ok(f) => f,
             Err(e) => {
-                eprintln!("Error reading message, skipping: {e}");
+                let e =
+                    ErrorReport::new(ErrorKind::ProtocolMismatch, format!("reading message: {e}"));
+                custom::report(&write_tx, e).await;
                 continue;
             }
         };
@@ -106,31 +97,69 @@ async fn main() {
         use IglooToExtension::*;
         match msg {
             DeviceCreated { name, id: did } => {
+                // one of a running node's sub-devices
+                let sub = pending_sub_devices.lock().await.remove(&name);
+                if let Some(sub) = sub {
+                    sub_devices.insert(did, sub.node);
+                    let res = cm
+                        .lock()
+                        .await
+                        .set_sub_device(sub.node, sub.device_id, did)
+                        .await;
+                    if let Err(e) = res {
+                        let e =
+                            ErrorReport::new(ErrorKind::Internal, format!("saving config: {e}"));
+                        custom::report(&write_tx, e.device(sub.node)).await;
+                    }
+                    // the node gave up waiting, tell it for its next connection
+                    if sub.reply.send(did).is_err()
+                        && let Some(node_tx) = device_txs.get(&sub.node)
+                    {
+                        let control = DeviceControl::SubDeviceCreated {
+                            device_id: sub.device_id,
+                            device: did,
+                        };
+                        let _ = node_tx.send(control).await;
+                    }
+                    continue;
+                }
+
                 // pull out pending device
                 let mut pc = pending_creation.lock().await;
                 let Some(mut device) = pc.remove(&name) else {
-                    eprintln!("Igloo sent DeviceCreated for unknown device. Skipping..");
+                    let e = ErrorReport::new(
+                        ErrorKind::InvalidParams,
+                        format!("Igloo created unknown device '{name}'"),
+                    );
+                    custom::report(&write_tx, e.device(did)).await;
                     continue;
                 };
                 drop(pc);
 
                 // save to disk
-                cm.config.devices.insert(did, device.params.clone());
-                cm.save().await.unwrap();
+                let mut cm_guard = cm.lock().await;
+                let res = cm_guard
+                    .set_device(did, device.params.clone())
+                    .await
+                    .map_err(|e| {
+                        ErrorReport::new(ErrorKind::Internal, format!("saving config: {e}"))
+                    });
+                // its level only applies now it has an ID
+                if let Err(e) = log_filter.apply_config(&cm_guard) {
+                    warn!(device = did, "keeping the previous log levels: {e}");
+                }
+                drop(cm_guard);
+                let res = CommandResult::new(custom::ADD_DEVICE, Some(did), res);
+                commands::reply(&write_tx, res).await;
 
                 // give actual ID now
                 device.id = did;
+                device.pending_sub_devices = Some(pending_sub_devices.clone());
 
                 // run
                 let (device_tx, device_rx) = kanal::bounded_async(50);
                 device_txs.insert(did, device_tx);
-                let write_tx_1 = write_tx.clone();
-                tokio::spawn(async move {
-                    let res = device.run(write_tx_1, device_rx).await;
-                    if let Err(e) = res {
-                        eprintln!("Device {did} crashed: {e}");
-                    }
-                });
+                tokio::spawn(run_device(device, write_tx.clone(), device_rx));
             }
 
             WriteComponents {
@@ -138,77 +167,217 @@ async fn main() {
                 entity,
                 comps,
             } => {
-                let Some(device) = device_txs.get(&did) else {
-                    eprintln!("Igloo sent write for unknown device '{did}'. Skipping..");
+                // sub-devices share their node's connection
+                let node = sub_devices.get(&did).copied().unwrap_or(did);
+                let Some(device) = device_txs.get(&node) else {
+                    let e = ErrorReport::new(ErrorKind::InvalidParams, "write to unknown device");
+                    custom::report(&write_tx, e.device(did)).await;
                     continue;
                 };
 
-                if let Err(e) = device.send((entity, comps)).await {
-                    eprintln!("Error sending message to device '{did}': {e}");
+                if let Err(e) = device.send(DeviceControl::Write(did, entity, comps)).await {
+                    let e =
+                        ErrorReport::new(ErrorKind::Internal, format!("device isn't running: {e}"));
+                    custom::report(&write_tx, e.device(did)).await;
                 }
             }
 
-            Custom { name, payload } => {
-                if name != "add_device" {
-                    eprintln!("Unknown custom command '{name}'. Skipping..");
+            Custom { name, payload } if name == custom::ADD_DEVICE => {
+                let Some(params) =
+                    commands::parse::<custom::AddDevice>(&write_tx, &name, payload).await
+                else {
+                    continue;
+                };
+                let params = params.into_params();
+
+                // validate up front, the rest only shows up once connecting
+                let valid = match &params.noise_psk {
+                    _ if params.ip.is_empty() => Err(ErrorReport::new(
+                        ErrorKind::InvalidParams,
+                        "ip can't be empty",
+                    )),
+                    Some(psk) => noise::decode_noise_psk(psk.expose())
+                        .map(|_| ())
+                        .map_err(ErrorReport::from),
+                    None => Ok(()),
+                };
+                if valid.is_err() {
+                    commands::reply(&write_tx, CommandResult::new(&name, None, valid)).await;
                     continue;
                 }
 
-                let params: ConnectionParams = serde_json::from_value(payload).unwrap();
-
-                let mut device = Device::new(0, params);
-                let write_tx_1 = write_tx.clone();
-                let pending_creation_1 = pending_creation.clone();
-                tokio::spawn(async move {
-                    let info = device.connect().await.unwrap();
-                    write_tx_1.create_device(info.name.clone()).await.unwrap();
-                    let mut pc = pending_creation_1.lock().await;
-                    pc.insert(info.name, device);
-                    drop(pc);
-                });
+                tokio::spawn(commands::add_device(
+                    pending_creation.clone(),
+                    write_tx.clone(),
+                    params,
+                ));
             }
-        }
-    }
-}
 
-impl ConfigManager {
-    async fn load() -> Result<Self, Box<dyn Error>> {
-        let path: PathBuf = [&ipc::get_data_path(), CONFIG_FILE].iter().collect();
+            Custom { name, payload }
+                if name == custom::SET_NOISE_PSK
+                    || name == custom::ROTATE_NOISE_PSK
+                    || name == custom::REMOVE_NOISE_PSK =>
+            {
+                let Some(cmd) =
+                    commands::parse::<custom::NoisePskCommand>(&write_tx, &name, payload).await
+                else {
+                    continue;
+                };
 
-        if !fs::try_exists(&path).await? {
-            fs::write(&path, "").await?;
-        }
+                // validate up front, so a bad key never reaches the device
+                let psk = match name.as_str() {
+                    custom::SET_NOISE_PSK => match cmd.psk {
+                        Some(psk) => noise::decode_noise_psk(psk.expose()).map(|_| Some(psk)),
+                        None => Err(ConnectionError::MissingNoisePsk),
+                    },
+                    custom::ROTATE_NOISE_PSK => {
+                        noise::generate_noise_psk().map(|psk| Some(Secret::new(psk)))
+                    }
+                    _ => Ok(None),
+                };
+                let psk = match psk {
+                    Ok(psk) => psk,
+                    Err(e) => {
+                        let res = CommandResult::new(&name, Some(cmd.device), Err(e.into()));
+                        commands::reply(&write_tx, res).await;
+                        continue;
+                    }
+                };
 
-        let mut file = File::options().read(true).write(true).open(&path).await?;
+                let Some(device_tx) = device_txs.get(&cmd.device).cloned() else {
+                    commands::unknown_device(&write_tx, &name, cmd.device).await;
+                    continue;
+                };
 
-        let meta = file.metadata().await?;
-        if meta.is_dir() {
-            return Err(format!("{} should not be directory", path.to_string_lossy()).into());
-        }
+                tokio::spawn(commands::change_noise_psk(
+                    cm.clone(),
+                    device_tx,
+                    write_tx.clone(),
+                    name,
+                    cmd.device,
+                    psk,
+                ));
+            }
 
-        if meta.is_symlink() {
-            let sym_meta = fs::symlink_metadata(&path).await?;
-            if sym_meta.is_dir() {
-                return Err(format!("{} should not be directory", path.to_string_lossy()).into());
+            Custom { name, payload } if name == custom::REMOVE_DEVICE => {
+                let Some(cmd) =
+                    commands::parse::<custom::DeviceCommand>(&write_tx, &name, payload).await
+                else {
+                    continue;
+                };
+
+                // stop routing writes to it right away
+                let Some(device_tx) = device_txs.remove(&cmd.device) else {
+                    commands::unknown_device(&write_tx, &name, cmd.device).await;
+                    continue;
+                };
+                sub_devices.retain(|_, node| *node != cmd.device);
+                // created after this, they're reported as unknown devices
+                pending_sub_devices
+                    .lock()
+                    .await
+                    .retain(|_, sub| sub.node != cmd.device);
+
+                tokio::spawn(commands::remove_device(
+                    cm.clone(),
+                    log_filter.clone(),
+                    device_tx,
+                    write_tx.clone(),
+                    cmd.device,
+                ));
             }
-        }
 
-        let mut content = String::with_capacity(meta.len() as usize);
-        file.read_to_string(&mut content).await?;
+            Custom { name, payload } if name == custom::UPDATE_DEVICE => {
+                let Some(cmd) =
+                    commands::parse::<custom::UpdateDevice>(&write_tx, &name, payload).await
+                else {
+                    continue;
+                };
 
-        Ok(Self {
-            file,
-            config: toml::from_str(&content)?,
-        })
-    }
+                // validate up front, so bad params never reach the device
+                let valid = match (&cmd.ip, &cmd.noise_psk) {
+                    (Some(ip), _) if ip.is_empty() => Err(ErrorReport::new(
+                        ErrorKind::InvalidParams,
+                        "ip can't be empty",
+                    )),
+                    (_, Some(psk)) => noise::decode_noise_psk(psk.expose())
+                        .map(|_| ())
+                        .map_err(ErrorReport::from),
+                    _ => Ok(()),
+                };
+                if valid.is_err() {
+                    let res = CommandResult::new(&name, Some(cmd.device), valid);
+                    commands::reply(&write_tx, res).await;
+                    continue;
+                }
+
+                let Some(device_tx) = device_txs.get(&cmd.device).cloned() else {
+                    commands::unknown_device(&write_tx, &name, cmd.device).await;
+                    continue;
+                };
+
+                tokio::spawn(commands::update_device(
+                    cm.clone(),
+                    log_filter.clone(),
+                    device_tx,
+                    write_tx.clone(),
+                    cmd,
+                ));
+            }
+
+            Custom { name, payload } if name == custom::RECONNECT_DEVICE => {
+                let Some(cmd) =
+                    commands::parse::<custom::DeviceCommand>(&write_tx, &name, payload).await
+                else {
+                    continue;
+                };
 
-    async fn save(&mut self) -> Result<(), Box<dyn Error>> {
-        let content = toml::to_string_pretty(&self.config)?;
-        self.file.seek(SeekFrom::Start(0)).await?;
-        self.file.write_all(content.as_bytes()).await?;
-        self.file.flush().await?;
-        self.file.set_len(content.len() as u64).await?;
+                let Some(device_tx) = device_txs.get(&cmd.device).cloned() else {
+                    commands::unknown_device(&write_tx, &name, cmd.device).await;
+                    continue;
+                };
 
-        Ok(())
+                tokio::spawn(commands::reconnect_device(
+                    device_tx,
+                    write_tx.clone(),
+                    cmd.device,
+                ));
+            }
+
+            Custom { name, .. } if name == custom::LIST_DEVICES => {
+                let device_txs = device_txs
+                    .iter()
+                    .map(|(did, device_tx)| (*did, device_tx.clone()))
+                    .collect();
+                tokio::spawn(commands::list_devices(
+                    cm.clone(),
+                    device_txs,
+                    write_tx.clone(),
+                ));
+            }
+
+            Custom { name, .. } => {
+                let e = ErrorReport::new(ErrorKind::InvalidParams, "unknown command");
+                let res = CommandResult::new(&name, None, Err(e));
+                commands::reply(&write_tx, res).await;
+            }
+        }
+    }
+}
+
+/// Run a device, reporting why if it stops for good
+async fn run_device(
+    device: Device,
+    igloo_tx: kanal::AsyncSender<ExtensionToIgloo>,
+    device_rx: kanal::AsyncReceiver<DeviceControl>,
+) {
+    let did = device.id;
+    let span = device.span();
+    if let Err(e) = device
+        .run(igloo_tx.clone(), device_rx)
+        .instrument(span)
+        .await
+    {
+        custom::report(&igloo_tx, ErrorReport::from(e).device(did)).await;
     }
 }
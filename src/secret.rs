@@ -0,0 +1,154 @@
+use crate::config::write_atomic;
+use async_trait::async_trait;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+use std::{error::Error, fmt, path::PathBuf};
+use tokio::fs;
+
+pub const SECRETS_FILE: &str = "secrets.toml";
+
+/// A password or key. Never shows up in `Debug` output.
+///
+/// Written either as a plain string, or as `{ env = "VAR" }` to read it
+/// from the environment. Environment backed secrets are never written
+/// anywhere else.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret {
+    value: String,
+    /// environment variable it came from
+    env: Option<String>,
+}
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self { value, env: None }
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+
+    /// Whether this belongs in a [`SecretStore`] (ie. isn't from the environment)
+    pub fn is_stored(&self) -> bool {
+        self.env.is_none()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.env {
+            Some(env) => write!(f, "Secret(env = {env})"),
+            None => f.write_str("Secret(<redacted>)"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SecretRepr {
+    Plain(String),
+    Env { env: String },
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.env {
+            Some(env) => SecretRepr::Env { env: env.clone() },
+            None => SecretRepr::Plain(self.value.clone()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // untagged errors don't echo the input, so the value can't leak from here
+        match SecretRepr::deserialize(deserializer)? {
+            SecretRepr::Plain(value) => Ok(Self::new(value)),
+            SecretRepr::Env { env } => match std::env::var(&env) {
+                Ok(value) => Ok(Self {
+                    value,
+                    env: Some(env),
+                }),
+                Err(_) => Err(D::Error::custom(format!(
+                    "environment variable `{env}` is not set"
+                ))),
+            },
+        }
+    }
+}
+
+/// `deserialize_with` for secrets in Igloo commands: only plain strings, so
+/// a command can't make the extension read `{ env = "..." }`
+pub fn plain<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Secret>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.map(Secret::new))
+}
+
+/// Secrets of one device, kept out of `config.toml`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DeviceSecrets {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_psk: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<Secret>,
+}
+
+impl DeviceSecrets {
+    pub fn is_empty(&self) -> bool {
+        self.noise_psk.is_none() && self.password.is_none()
+    }
+}
+
+/// Where `ConfigManager` keeps secrets
+#[async_trait]
+pub trait SecretStore: fmt::Debug + Send + Sync {
+    /// maps Persistent Igloo Device ID -> Secrets
+    async fn load(&mut self) -> Result<FxHashMap<u64, DeviceSecrets>, Box<dyn Error>>;
+    async fn save(&mut self, secrets: &FxHashMap<u64, DeviceSecrets>)
+    -> Result<(), Box<dyn Error>>;
+}
+
+/// Plain TOML file only readable by the current user
+#[derive(Debug)]
+pub struct FileSecretStore {
+    path: PathBuf,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SecretsFile {
+    #[serde(default, rename = "device")]
+    devices: FxHashMap<u64, DeviceSecrets>,
+}
+
+impl FileSecretStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl SecretStore for FileSecretStore {
+    async fn load(&mut self) -> Result<FxHashMap<u64, DeviceSecrets>, Box<dyn Error>> {
+        if !fs::try_exists(&self.path).await? {
+            return Ok(FxHashMap::default());
+        }
+
+        let content = fs::read_to_string(&self.path).await?;
+        let file: SecretsFile = toml::from_str(&content)
+            .map_err(|_| format!("{} is not valid TOML", self.path.to_string_lossy()))?;
+        Ok(file.devices)
+    }
+
+    async fn save(
+        &mut self,
+        secrets: &FxHashMap<u64, DeviceSecrets>,
+    ) -> Result<(), Box<dyn Error>> {
+        let content = toml::to_string_pretty(&SecretsFile {
+            devices: secrets.clone(),
+        })?;
+
+        write_atomic(&self.path, content.as_bytes(), 0o600).await?;
+
+        Ok(())
+    }
+}